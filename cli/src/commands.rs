@@ -3,6 +3,7 @@ use anchor_client::{
     Program,
     solana_client::rpc_client::RpcClient,
     solana_sdk::{
+        instruction::{AccountMeta, Instruction},
         signature::{read_keypair_file, Keypair}, 
         signer::Signer,
         system_program,
@@ -15,6 +16,8 @@ use anchor_client::{
 use spl_associated_token_account::get_associated_token_address;
 use anchor_lang::prelude::*;
 use std::rc::Rc;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use kamino_lend::state::Reserve;
 
 use crate::consts::*;
 use crate::http_client::KeeperHttp;
@@ -33,12 +36,14 @@ pub fn init(keypair_path: std::path::PathBuf) -> Result<()> {
     println!("Marginfi account: {}", marginfi_account.pubkey().to_string());
 
     let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
+    let markets = get_usdc_markets(&program)?;
 
-    let user_vault_pda: Pubkey = get_user_vault_pda(kp.pubkey());
+    let user_vault_pda: Pubkey = get_new_user_vault_pda(kp.pubkey());
+    println!("User Vault PDA: {}", user_vault_pda.to_string());
 
     let kaminio_usdc_colateral_vault = get_associated_token_address(
         &user_vault_pda,
-        &Pubkey::from_str_const(KLEND_COLLATERAL_MINT));
+        &markets.kamino_collateral_mint);
 
     let user_usdc_vault_ata = get_associated_token_address(
         &user_vault_pda, 
@@ -52,12 +57,13 @@ pub fn init(keypair_path: std::path::PathBuf) -> Result<()> {
             user_vault_account: user_vault_pda,
             user_usdc_vault: user_usdc_vault_ata,
             kamino_usdc_collateral_vault: kaminio_usdc_colateral_vault,
-            kamino_usdc_collateral_mint: Pubkey::from_str_const(KLEND_COLLATERAL_MINT),
+            kamino_usdc_collateral_mint: markets.kamino_collateral_mint,
             marginfi_account: marginfi_account.pubkey(),
-            marginfi_group: Pubkey::from_str_const(MARGINFI_GROUP),
+            marginfi_group: markets.marginfi_group,
             marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
             system_program: system_program::ID,
             token_program: spl_token::ID,
+            collateral_token_program: spl_token::ID,
             associated_token_program: spl_associated_token_account::ID,
             rent: sysvar::rent::ID,
         })
@@ -72,7 +78,6 @@ pub fn init(keypair_path: std::path::PathBuf) -> Result<()> {
 
 
 pub fn deposit(keypair_path: std::path::PathBuf, amount: u64) -> Result<()> {
-    // Stage 1: deposit to vault ATA
     let kp = read_keypair_file(&keypair_path)
     .map_err(|e| anyhow!("could not read file `{}`: {}", keypair_path.display(), e))?;
     let public_key = kp.pubkey();
@@ -95,10 +100,57 @@ pub fn deposit(keypair_path: std::path::PathBuf, amount: u64) -> Result<()> {
 
     println!("User USDC Vault ATA: {}", user_usdc_vault_ata.to_string());
 
-    // Build and send instructions
+    // A vault that already has funds deployed takes the deposit straight into its protocol in one transaction
+    let vault: yield_vault::accounts::UserVault = program.account(user_vault_pda)?;
+    if vault.active_protocol != yield_vault::types::Protocol::None {
+        let markets = get_usdc_markets(&program)?;
+        let config: yield_vault::accounts::VaultConfig = program.account(get_config_pda())?;
+        let mut request = program.request();
+        if vault.active_protocol != yield_vault::types::Protocol::Marginfi {
+            // Kamino needs the reserve refreshed earlier in the same transaction
+            request = request.instruction(get_refresh_reserve_ix(&program, &markets)?);
+        }
+        let ix = program.request().accounts(
+            accounts::DepositAndDeploy {
+                user: public_key,
+                config: get_config_pda(),
+                registry: get_registry_pda(),
+                usdc_mint: Pubkey::from_str_const(USDC_MINT),
+                treasury_usdc_ta: get_treasury_usdc_ta(&config),
+                user_vault_account: user_vault_pda,
+                user_usdc_ta,
+                user_usdc_vault_ata,
+                kamino_program: Pubkey::from_str_const(KLEND_PROGRAM),
+                kamino_lending_market: markets.kamino_lending_market,
+                kamino_lending_market_authority: get_kamino_market_authority(&markets.kamino_lending_market),
+                kamino_reserve: markets.kamino_reserve,
+                kamino_reserve_liquidity_supply: markets.kamino_liquidity_supply,
+                kamino_usdc_collateral_mint: markets.kamino_collateral_mint,
+                kamino_usdc_collateral_vault: get_associated_token_address(&user_vault_pda, &markets.kamino_collateral_mint),
+                marginfi_group: markets.marginfi_group,
+                marginfi_account: vault.marginfi_account,
+                marginfi_bank: markets.marginfi_bank,
+                marginfi_bank_liquidity_vault_authority: get_marginfi_vault_authority(&markets.marginfi_bank),
+                marginfi_bank_liquidity_vault: markets.marginfi_liquidity_vault,
+                marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
+                token_program: spl_token::ID,
+                collateral_token_program: spl_token::ID,
+                instruction_sysvar_account: sysvar::instructions::ID,
+            })
+            // The whole deposit is deployed, so it should land worth `amount` less rounding
+            .args(args::DepositAndDeploy { amount, min_out: min_out(amount) })
+            .instructions()?
+            .remove(0);
+        let signature = request.instruction(ix).signer(kp).send()?;
+        println!("✅ Deposit and deploy Transaction signature: {}", signature.to_string());
+        return Ok(());
+    }
+
+    // Stage 1: deposit to vault ATA
     let tx = program.request().accounts(
         accounts::Deposit {
             user: public_key,
+            config: get_config_pda(),
             usdc_mint: Pubkey::from_str_const(USDC_MINT),
             user_vault_account: user_vault_pda,
             user_usdc_ta: user_usdc_ta,
@@ -114,7 +166,7 @@ pub fn deposit(keypair_path: std::path::PathBuf, amount: u64) -> Result<()> {
     let signature = program.request().instruction(tx).signer(kp).send()?;
     println!("✅ Deposit Transaction signature: {}", signature.to_string());
 
-     // Stage 2: call keeper to deploy from vault ATA to Lending Protocol
+     // Stage 2: nothing is deployed yet, so the keeper picks the protocols and deploys from the vault ATA
      let http = KeeperHttp::new(keeper_url())?;
      let resp = http.deposit(&public_key.to_string(), amount)?;
      println!("✅ Keeper deploy: {} (protocol={}, vault={})", resp.tx, resp.protocol, resp.vault);
@@ -129,12 +181,7 @@ pub fn withdraw(keypair_path: std::path::PathBuf) -> Result<()> {
     let public_key = kp.pubkey();
     println!("Withdraw for Public key: {}", public_key.to_string());
 
-    // Stage 1: redeem from Lending Protocol to vault ATA
     let http = KeeperHttp::new(keeper_url())?;
-    let resp = http.withdraw(&public_key.to_string())?;
-    println!("✅ Keeper unwind: {}", resp.tx);
-
-    // Stage 2: withdraw from vault ATA to USDC ATA
     let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
     let user_vault_pda: Pubkey = get_user_vault_pda(kp.pubkey());
 
@@ -149,6 +196,60 @@ pub fn withdraw(keypair_path: std::path::PathBuf) -> Result<()> {
         &Pubkey::from_str_const(USDC_MINT));
     println!("User USDC Vault ATA: {}", user_usdc_vault_ata.to_string());
 
+    // A vault with funds deployed redeems and pays out in one transaction, so nothing is left
+    // between a keeper unwind and reading the vault ATA balance
+    let vault: yield_vault::accounts::UserVault = program.account(user_vault_pda)?;
+    if vault.active_protocol != yield_vault::types::Protocol::None {
+        let markets = get_usdc_markets(&program)?;
+        let config: yield_vault::accounts::VaultConfig = program.account(get_config_pda())?;
+        // Everything the vault holds; the program caps the payout at what is left after fees
+        let amount = get_position(&program, public_key, user_vault_pda, &vault)?.total;
+        println!("User Vault Value: {}", amount);
+        let mut request = program.request();
+        if vault.active_protocol != yield_vault::types::Protocol::Marginfi {
+            // Kamino needs the reserve refreshed earlier in the same transaction
+            request = request.instruction(get_refresh_reserve_ix(&program, &markets)?);
+        }
+        let ix = program.request().accounts(
+            accounts::WithdrawFromProtocol {
+                user: public_key,
+                config: get_config_pda(),
+                registry: get_registry_pda(),
+                usdc_mint: Pubkey::from_str_const(USDC_MINT),
+                treasury_usdc_ta: get_treasury_usdc_ta(&config),
+                user_vault_account: user_vault_pda,
+                user_usdc_ta,
+                user_usdc_vault_ata,
+                kamino_program: Pubkey::from_str_const(KLEND_PROGRAM),
+                kamino_lending_market: markets.kamino_lending_market,
+                kamino_lending_market_authority: get_kamino_market_authority(&markets.kamino_lending_market),
+                kamino_reserve: markets.kamino_reserve,
+                kamino_reserve_liquidity_supply: markets.kamino_liquidity_supply,
+                kamino_usdc_collateral_mint: markets.kamino_collateral_mint,
+                kamino_usdc_collateral_vault: get_associated_token_address(&user_vault_pda, &markets.kamino_collateral_mint),
+                marginfi_group: markets.marginfi_group,
+                marginfi_account: vault.marginfi_account,
+                marginfi_bank: markets.marginfi_bank,
+                marginfi_bank_liquidity_vault_authority: get_marginfi_vault_authority(&markets.marginfi_bank),
+                marginfi_bank_liquidity_vault: markets.marginfi_liquidity_vault,
+                marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
+                token_program: spl_token::ID,
+                collateral_token_program: spl_token::ID,
+                instruction_sysvar_account: sysvar::instructions::ID,
+            })
+            .args(args::WithdrawFromProtocol { amount })
+            .instructions()?
+            .remove(0);
+        let signature = request.instruction(ix).signer(kp).send()?;
+        println!("✅ Withdraw from protocol Transaction signature: {}", signature.to_string());
+
+        match http.delete_lender(&public_key.to_string()) {
+            Ok(resp) => println!("✅ Delete lender {}: {}", public_key.to_string(), resp.status()),
+            Err(e) => println!("⚠️ Delete lender failed: {}", e),
+        }
+        return Ok(());
+    }
+
     let amount = spl_balance(user_usdc_vault_ata)?;
     println!("User USDC Vault Balance: {}", amount);
 
@@ -156,6 +257,7 @@ pub fn withdraw(keypair_path: std::path::PathBuf) -> Result<()> {
     let tx = program.request().accounts(
         accounts::Withdraw {
             user: public_key,
+            config: get_config_pda(),
             usdc_mint: Pubkey::from_str_const(USDC_MINT),
             user_vault_account: user_vault_pda,
             user_usdc_ta: user_usdc_ta,
@@ -171,21 +273,497 @@ pub fn withdraw(keypair_path: std::path::PathBuf) -> Result<()> {
     let signature = program.request().instruction(tx).signer(kp).send()?;
     println!("✅ Withdraw Transaction signature: {}", signature.to_string());
 
+    match http.delete_lender(&public_key.to_string()) {
+        Ok(resp) => println!("✅ Delete lender {}: {}", public_key.to_string(), resp.status()),
+        Err(e) => println!("⚠️ Delete lender failed: {}", e),
+    }
+    Ok(())
+}
+
+
+pub fn pool_deposit(keypair_path: std::path::PathBuf, amount: u64) -> Result<()> {
+    let kp = read_keypair_file(&keypair_path)
+    .map_err(|e| anyhow!("could not read file `{}`: {}", keypair_path.display(), e))?;
+    let public_key = kp.pubkey();
+    println!("Pool deposit for Public key: {}", public_key.to_string());
+
+    let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
+    let usdc_mint = Pubkey::from_str_const(USDC_MINT);
+    let markets = get_usdc_markets(&program)?;
+    let config: yield_vault::accounts::VaultConfig = program.account(get_config_pda())?;
+    let (vault_state, share_mint) = get_pool_pdas(usdc_mint);
+    let pool: yield_vault::accounts::VaultState = program.account(vault_state)?;
+
+    // Shares are priced off the pool's live value, which reads the refreshed Kamino reserve
+    let tx = program.request().accounts(
+        accounts::DepositShares {
+            user: public_key,
+            config: get_config_pda(),
+            usdc_mint,
+            vault_state,
+            share_mint,
+            user_usdc_ta: get_associated_token_address(&public_key, &usdc_mint),
+            user_share_ta: get_associated_token_address(&public_key, &share_mint),
+            pool_usdc_ata: get_associated_token_address(&vault_state, &usdc_mint),
+            registry: get_registry_pda(),
+            treasury_usdc_ta: get_treasury_usdc_ta(&config),
+            kamino_program: Pubkey::from_str_const(KLEND_PROGRAM),
+            kamino_lending_market: markets.kamino_lending_market,
+            kamino_lending_market_authority: get_kamino_market_authority(&markets.kamino_lending_market),
+            kamino_reserve: markets.kamino_reserve,
+            kamino_reserve_liquidity_supply: markets.kamino_liquidity_supply,
+            kamino_usdc_collateral_mint: markets.kamino_collateral_mint,
+            pool_collateral_vault: get_associated_token_address(&vault_state, &markets.kamino_collateral_mint),
+            marginfi_group: markets.marginfi_group,
+            marginfi_account: pool.marginfi_account,
+            marginfi_bank: markets.marginfi_bank,
+            marginfi_bank_liquidity_vault_authority: get_marginfi_vault_authority(&markets.marginfi_bank),
+            marginfi_bank_liquidity_vault: markets.marginfi_liquidity_vault,
+            marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
+            token_program: spl_token::ID,
+            collateral_token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+            system_program: system_program::ID,
+            instruction_sysvar_account: sysvar::instructions::ID,
+        })
+        .args(args::DepositShares{amount:amount})
+        .instructions()?
+        .remove(0);
+
+    let signature = program.request()
+        .instruction(get_refresh_reserve_ix(&program, &markets)?)
+        .instruction(tx)
+        .signer(kp)
+        .send()?;
+    println!("✅ Pool Deposit Transaction signature: {}", signature.to_string());
+    Ok(())
+}
+
+
+pub fn pool_withdraw(keypair_path: std::path::PathBuf, shares: u64) -> Result<()> {
+    let kp = read_keypair_file(&keypair_path)
+    .map_err(|e| anyhow!("could not read file `{}`: {}", keypair_path.display(), e))?;
+    let public_key = kp.pubkey();
+    println!("Pool withdraw for Public key: {}", public_key.to_string());
+
+    let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
+    let usdc_mint = Pubkey::from_str_const(USDC_MINT);
+    let markets = get_usdc_markets(&program)?;
+    let config: yield_vault::accounts::VaultConfig = program.account(get_config_pda())?;
+    let (vault_state, share_mint) = get_pool_pdas(usdc_mint);
+    let pool: yield_vault::accounts::VaultState = program.account(vault_state)?;
+
+    // The program redeems whatever idle pool USDC can't cover, so the reserve is refreshed first
+    let tx = program.request().accounts(
+        accounts::WithdrawShares {
+            user: public_key,
+            config: get_config_pda(),
+            usdc_mint,
+            vault_state,
+            share_mint,
+            user_usdc_ta: get_associated_token_address(&public_key, &usdc_mint),
+            user_share_ta: get_associated_token_address(&public_key, &share_mint),
+            pool_usdc_ata: get_associated_token_address(&vault_state, &usdc_mint),
+            registry: get_registry_pda(),
+            treasury_usdc_ta: get_treasury_usdc_ta(&config),
+            kamino_program: Pubkey::from_str_const(KLEND_PROGRAM),
+            kamino_lending_market: markets.kamino_lending_market,
+            kamino_lending_market_authority: get_kamino_market_authority(&markets.kamino_lending_market),
+            kamino_reserve: markets.kamino_reserve,
+            kamino_reserve_liquidity_supply: markets.kamino_liquidity_supply,
+            kamino_usdc_collateral_mint: markets.kamino_collateral_mint,
+            pool_collateral_vault: get_associated_token_address(&vault_state, &markets.kamino_collateral_mint),
+            marginfi_group: markets.marginfi_group,
+            marginfi_account: pool.marginfi_account,
+            marginfi_bank: markets.marginfi_bank,
+            marginfi_bank_liquidity_vault_authority: get_marginfi_vault_authority(&markets.marginfi_bank),
+            marginfi_bank_liquidity_vault: markets.marginfi_liquidity_vault,
+            marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
+            token_program: spl_token::ID,
+            collateral_token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+            system_program: system_program::ID,
+            instruction_sysvar_account: sysvar::instructions::ID,
+        })
+        .args(args::WithdrawShares{shares:shares})
+        .instructions()?
+        .remove(0);
+
+    let signature = program.request()
+        .instruction(get_refresh_reserve_ix(&program, &markets)?)
+        .instruction(tx)
+        .signer(kp)
+        .send()?;
+    println!("✅ Pool Withdraw Transaction signature: {}", signature.to_string());
+    Ok(())
+}
+
+
+// Moves a legacy per-user vault into the pool: keeper unwinds it, then the user swaps idle USDC for shares.
+pub fn migrate(keypair_path: std::path::PathBuf) -> Result<()> {
+    let kp = read_keypair_file(&keypair_path)
+    .map_err(|e| anyhow!("could not read file `{}`: {}", keypair_path.display(), e))?;
+    let public_key = kp.pubkey();
+    println!("Migrate to pool for Public key: {}", public_key.to_string());
+
+    // Stage 1: redeem from Lending Protocol to vault ATA
+    // (if the keeper is down, `emergency-redeem` does this step and the idle balance is withdrawn below)
+    let http = KeeperHttp::new(keeper_url())?;
+    match http.withdraw(&public_key.to_string()) {
+        Ok(resp) => println!("✅ Keeper unwind: {}", resp.tx),
+        Err(e) => println!("⚠️ Keeper unwind failed, withdrawing idle balance only: {}", e),
+    }
+
+    // Stage 2: move vault ATA balance into the pool for shares
+    let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
+    let usdc_mint = Pubkey::from_str_const(USDC_MINT);
+    let markets = get_usdc_markets(&program)?;
+    let config: yield_vault::accounts::VaultConfig = program.account(get_config_pda())?;
+    let user_vault_pda: Pubkey = get_user_vault_pda(public_key);
+    let (vault_state, share_mint) = get_pool_pdas(usdc_mint);
+    let pool: yield_vault::accounts::VaultState = program.account(vault_state)?;
+
+    let tx = program.request().accounts(
+        accounts::MigrateToPool {
+            user: public_key,
+            config: get_config_pda(),
+            usdc_mint,
+            user_vault_account: user_vault_pda,
+            user_usdc_vault_ata: get_associated_token_address(&user_vault_pda, &usdc_mint),
+            kamino_usdc_collateral_vault: get_associated_token_address(&user_vault_pda, &markets.kamino_collateral_mint),
+            vault_state,
+            share_mint,
+            user_share_ta: get_associated_token_address(&public_key, &share_mint),
+            pool_usdc_ata: get_associated_token_address(&vault_state, &usdc_mint),
+            registry: get_registry_pda(),
+            treasury_usdc_ta: get_treasury_usdc_ta(&config),
+            kamino_program: Pubkey::from_str_const(KLEND_PROGRAM),
+            kamino_lending_market: markets.kamino_lending_market,
+            kamino_lending_market_authority: get_kamino_market_authority(&markets.kamino_lending_market),
+            kamino_reserve: markets.kamino_reserve,
+            kamino_reserve_liquidity_supply: markets.kamino_liquidity_supply,
+            kamino_usdc_collateral_mint: markets.kamino_collateral_mint,
+            pool_collateral_vault: get_associated_token_address(&vault_state, &markets.kamino_collateral_mint),
+            marginfi_group: markets.marginfi_group,
+            marginfi_account: pool.marginfi_account,
+            marginfi_bank: markets.marginfi_bank,
+            marginfi_bank_liquidity_vault_authority: get_marginfi_vault_authority(&markets.marginfi_bank),
+            marginfi_bank_liquidity_vault: markets.marginfi_liquidity_vault,
+            marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
+            token_program: spl_token::ID,
+            collateral_token_program: spl_token::ID,
+            associated_token_program: spl_associated_token_account::ID,
+            system_program: system_program::ID,
+            instruction_sysvar_account: sysvar::instructions::ID,
+        })
+        .args(args::MigrateToPool)
+        .instructions()?
+        .remove(0);
+
+    // Shares are priced off the pool's live value, which reads the refreshed Kamino reserve
+    let signature = program.request()
+        .instruction(get_refresh_reserve_ix(&program, &markets)?)
+        .instruction(tx)
+        .signer(kp)
+        .send()?;
+    println!("✅ Migrate Transaction signature: {}", signature.to_string());
+
     let resp = http.delete_lender(&public_key.to_string())?;
     println!("✅ Delete lender {}: {}", public_key.to_string(), resp.status());
     Ok(())
 }
 
 
+// Escape hatch: redeem the vault from its active protocol without the keeper.
+// Follow with `withdraw` once the funds are back in the vault ATA.
+pub fn emergency_redeem(keypair_path: std::path::PathBuf) -> Result<()> {
+    let kp = read_keypair_file(&keypair_path)
+    .map_err(|e| anyhow!("could not read file `{}`: {}", keypair_path.display(), e))?;
+    let public_key = kp.pubkey();
+    println!("Emergency redeem for Public key: {}", public_key.to_string());
+
+    let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
+    let usdc_mint = Pubkey::from_str_const(USDC_MINT);
+    let markets = get_usdc_markets(&program)?;
+    let user_vault_pda: Pubkey = get_user_vault_pda(public_key);
+    let vault: yield_vault::accounts::UserVault = program.account(user_vault_pda)?;
+    let config: yield_vault::accounts::VaultConfig = program.account(get_config_pda())?;
+    let treasury_usdc_ta = get_treasury_usdc_ta(&config);
+    let user_usdc_vault_ata = get_associated_token_address(&user_vault_pda, &usdc_mint);
+
+    // A split vault is redeemed from both protocols in one transaction
+    let (from_kamino, from_marginfi) = match vault.active_protocol {
+        yield_vault::types::Protocol::Kamino   => (true, false),
+        yield_vault::types::Protocol::Marginfi => (false, true),
+        yield_vault::types::Protocol::Split    => (true, true),
+        yield_vault::types::Protocol::None     => {
+            println!("Nothing deployed, funds are already in the vault ATA");
+            return Ok(());
+        }
+    };
+    let mut request = program.request();
+    if from_kamino {
+        // Kamino needs the reserve refreshed earlier in the same transaction
+        request = request.instruction(get_refresh_reserve_ix(&program, &markets)?);
+        let ix = program.request().accounts(
+            accounts::EmergencyRedeemKamino {
+                user: public_key,
+                config: get_config_pda(),
+                usdc_mint,
+                treasury_usdc_ta,
+                user_vault_account: user_vault_pda,
+                user_usdc_vault_ata,
+                kamino_program: Pubkey::from_str_const(KLEND_PROGRAM),
+                kamino_lending_market: markets.kamino_lending_market,
+                kamino_lending_market_authority: get_kamino_market_authority(&markets.kamino_lending_market),
+                kamino_reserve: markets.kamino_reserve,
+                kamino_reserve_liquidity_supply: markets.kamino_liquidity_supply,
+                kamino_usdc_collateral_mint: markets.kamino_collateral_mint,
+                kamino_usdc_collateral_vault: get_associated_token_address(&user_vault_pda, &markets.kamino_collateral_mint),
+                associated_token_program: spl_associated_token_account::ID,
+                system_program: system_program::ID,
+                token_program: spl_token::ID,
+                collateral_token_program: spl_token::ID,
+                rent: sysvar::rent::ID,
+                instruction_sysvar_account: sysvar::instructions::ID,
+            })
+            .args(args::EmergencyRedeemKamino)
+            .instructions()?
+            .remove(0);
+        request = request.instruction(ix);
+    }
+    if from_marginfi {
+        let ix = program.request().accounts(
+            accounts::EmergencyRedeemMarginfi {
+                user: public_key,
+                config: get_config_pda(),
+                usdc_mint,
+                treasury_usdc_ta,
+                user_vault_account: user_vault_pda,
+                user_usdc_vault_ata,
+                marginfi_group: markets.marginfi_group,
+                marginfi_account: vault.marginfi_account,
+                marginfi_bank: markets.marginfi_bank,
+                marginfi_bank_liquidity_vault_authority: get_marginfi_vault_authority(&markets.marginfi_bank),
+                marginfi_bank_liquidity_vault: markets.marginfi_liquidity_vault,
+                marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
+                token_program: spl_token::ID,
+            })
+            .args(args::EmergencyRedeemMarginfi)
+            .instructions()?
+            .remove(0);
+        request = request.instruction(ix);
+    }
+
+    let signature = request.signer(kp).send()?;
+    println!("✅ Emergency Redeem Transaction signature: {}", signature.to_string());
+    Ok(())
+}
+
+pub fn close(keypair_path: std::path::PathBuf) -> Result<()> {
+    let kp = read_keypair_file(&keypair_path)
+    .map_err(|e| anyhow!("could not read file `{}`: {}", keypair_path.display(), e))?;
+    let public_key = kp.pubkey();
+    println!("Close vault for Public key: {}", public_key.to_string());
+
+    let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
+    let usdc_mint = Pubkey::from_str_const(USDC_MINT);
+    let user_vault_pda: Pubkey = get_user_vault_pda(public_key);
+    let vault: yield_vault::accounts::UserVault = program.account(user_vault_pda)?;
+    let collateral_mint = get_usdc_markets(&program)?.kamino_collateral_mint;
+
+    // The program only closes empty vaults: run `withdraw` first
+    let tx = program.request().accounts(
+        accounts::CloseVault {
+            user: public_key,
+            config: get_config_pda(),
+            usdc_mint,
+            user_vault_account: user_vault_pda,
+            user_usdc_vault_ata: get_associated_token_address(&user_vault_pda, &usdc_mint),
+            kamino_usdc_collateral_mint: collateral_mint,
+            kamino_usdc_collateral_vault: get_associated_token_address(&user_vault_pda, &collateral_mint),
+            marginfi_account: vault.marginfi_account,
+            marginfi_program: Pubkey::from_str_const(MARGINFI_PROGRAM),
+            token_program: spl_token::ID,
+            collateral_token_program: spl_token::ID,
+        })
+        .args(args::CloseVault)
+        .instructions()?
+        .remove(0);
+
+    let signature = program.request().instruction(tx).signer(kp).send()?;
+    println!("✅ Close Vault Transaction signature: {}", signature.to_string());
+    Ok(())
+}
+
+pub fn status(keypair_path: std::path::PathBuf) -> Result<()> {
+    let kp = read_keypair_file(&keypair_path)
+    .map_err(|e| anyhow!("could not read file `{}`: {}", keypair_path.display(), e))?;
+    let public_key = kp.pubkey();
+    let program: Program<Rc<Keypair>> = get_program(kp.insecure_clone())?;
+    let user_vault_pda: Pubkey = get_user_vault_pda(public_key);
+
+    let vault: yield_vault::accounts::UserVault = program.account(user_vault_pda)?;
+    let position = get_position(&program, public_key, user_vault_pda, &vault)?;
+    println!("Vault {} of {}", user_vault_pda.to_string(), public_key.to_string());
+    println!("  Active protocol: {:?} (Kamino weight {} bps)", vault.active_protocol, vault.kamino_weight_bps);
+    println!("  Principal:       {}", vault.deposited_amount);
+    println!("  Withdrawn:       {}", vault.withdrawn_amount);
+    println!("  Realized yield:  {}", vault.realized_yield);
+    println!("  Idle USDC:       {}", position.idle);
+    println!("  Kamino value:    {}", position.kamino);
+    println!("  Marginfi value:  {}", position.marginfi);
+    println!("  Total value:     {}", position.total);
+    Ok(())
+}
+
+// Simulates the read-only `get_position` instruction and decodes the Position it returns.
+fn get_position(
+    program: &Program<Rc<Keypair>>,
+    user: Pubkey,
+    user_vault_pda: Pubkey,
+    vault: &yield_vault::accounts::UserVault,
+) -> Result<yield_vault::types::Position> {
+    let usdc_mint = Pubkey::from_str_const(USDC_MINT);
+    let markets = get_usdc_markets(program)?;
+    let kamino_collateral_mint = markets.kamino_collateral_mint;
+    let tx = program.request().accounts(
+        accounts::GetPosition {
+            user,
+            usdc_mint,
+            user_vault_account: user_vault_pda,
+            user_usdc_vault_ata: get_associated_token_address(&user_vault_pda, &usdc_mint),
+            kamino_reserve: markets.kamino_reserve,
+            kamino_usdc_collateral_mint: kamino_collateral_mint,
+            kamino_usdc_collateral_vault: get_associated_token_address(&user_vault_pda, &kamino_collateral_mint),
+            marginfi_account: vault.marginfi_account,
+            marginfi_bank: markets.marginfi_bank,
+            token_program: spl_token::ID,
+            collateral_token_program: spl_token::ID,
+        })
+        .args(args::GetPosition)
+        .signed_transaction()?;
+    let sim = program.rpc().simulate_transaction(&tx)?.value;
+    if let Some(err) = sim.err {
+        return Err(anyhow!("get_position simulation failed: {:?}, logs: {:?}", err, sim.logs));
+    }
+    let return_data = sim.return_data.ok_or_else(|| anyhow!("get_position returned no data"))?;
+    let bytes = BASE64.decode(&return_data.data.0)?;
+    Ok(yield_vault::types::Position::try_from_slice(&bytes)?)
+}
+
+fn get_config_pda() -> Pubkey {
+    let (config, _bump) = Pubkey::find_program_address(&[b"config"], &yield_vault::ID);
+    config
+}
+
+fn get_registry_pda() -> Pubkey {
+    let (registry, _bump) = Pubkey::find_program_address(&[b"registry"], &yield_vault::ID);
+    registry
+}
+
+// USDC's Kamino reserve and Marginfi bank, as approved in the program's ProtocolRegistry.
+fn get_usdc_markets(program: &Program<Rc<Keypair>>) -> Result<yield_vault::types::RegistryEntry> {
+    let registry: yield_vault::accounts::ProtocolRegistry = program.account(get_registry_pda())?;
+    let usdc_mint = Pubkey::from_str_const(USDC_MINT);
+    registry.entries.into_iter()
+        .find(|e| e.mint == usdc_mint)
+        .ok_or_else(|| anyhow!("no USDC markets in the protocol registry"))
+}
+
+// klend `refresh_reserve` for the USDC reserve, with the oracles configured on the reserve
+// (unset ones are passed as the klend program id).
+fn get_refresh_reserve_ix(program: &Program<Rc<Keypair>>, markets: &yield_vault::types::RegistryEntry) -> Result<Instruction> {
+    let klend_program = Pubkey::from_str_const(KLEND_PROGRAM);
+    let account = program.rpc().get_account(&markets.kamino_reserve)?;
+    let data = account.data.get(8..8 + std::mem::size_of::<Reserve>())
+        .ok_or_else(|| anyhow!("reserve {} has an unexpected size", markets.kamino_reserve))?;
+    let reserve: Reserve = bytemuck::pod_read_unaligned(data);
+    let token_info = &reserve.config.token_info;
+    let oracle = |key: Pubkey| {
+        let unset = key == Pubkey::default() || key == Pubkey::from_str_const(KLEND_NULL_PUBKEY);
+        AccountMeta::new_readonly(if unset { klend_program } else { key }, false)
+    };
+    Ok(Instruction {
+        program_id: klend_program,
+        accounts: vec![
+            AccountMeta::new(markets.kamino_reserve, false),
+            AccountMeta::new_readonly(markets.kamino_lending_market, false),
+            oracle(token_info.pyth_configuration.price),
+            oracle(token_info.switchboard_configuration.price_aggregator),
+            oracle(token_info.switchboard_configuration.twap_aggregator),
+            oracle(token_info.scope_configuration.price_feed),
+        ],
+        data: KLEND_REFRESH_RESERVE_DISCRIMINATOR.to_vec(),
+    })
+}
+
+// Fees are settled on full redeems, vault payouts and pool share moves, so the treasury account
+// is needed once fees are configured.
+fn get_treasury_usdc_ta(config: &yield_vault::accounts::VaultConfig) -> Option<Pubkey> {
+    (config.management_fee_bps > 0 || config.performance_fee_bps > 0)
+        .then(|| get_associated_token_address(&config.treasury, &Pubkey::from_str_const(USDC_MINT)))
+}
+
+fn get_kamino_market_authority(lending_market: &Pubkey) -> Pubkey {
+    let (authority, _bump) = Pubkey::find_program_address(
+        &[b"lma", lending_market.as_ref()],
+        &Pubkey::from_str_const(KLEND_PROGRAM)
+    );
+    authority
+}
+
+fn get_marginfi_vault_authority(bank: &Pubkey) -> Pubkey {
+    let (authority, _bump) = Pubkey::find_program_address(
+        &[b"liquidity_vault_auth", bank.as_ref()],
+        &Pubkey::from_str_const(MARGINFI_PROGRAM)
+    );
+    authority
+}
+
+fn get_pool_pdas(usdc_mint: Pubkey) -> (Pubkey, Pubkey) {
+    let (vault_state, _bump) = Pubkey::find_program_address(
+        &[b"vault_state", usdc_mint.as_ref()],
+        &yield_vault::ID
+    );
+    let (share_mint, _bump) = Pubkey::find_program_address(
+        &[b"share_mint", vault_state.as_ref()],
+        &yield_vault::ID
+    );
+    println!("Pool VaultState PDA: {}, share mint: {}", vault_state.to_string(), share_mint.to_string());
+    (vault_state, share_mint)
+}
+
+// Vaults are keyed by (owner, mint). Vaults created before multi-mint support still live at
+// the legacy [b"vault", owner] address, which is used as long as no mint-keyed vault exists.
 fn get_user_vault_pda(user: Pubkey) -> Pubkey {
-    let (user_vault_pda, _bump) = Pubkey::find_program_address(
+    let user_vault_pda = get_new_user_vault_pda(user);
+    let (legacy_pda, _bump) = Pubkey::find_program_address(
         &[b"vault", user.as_ref()],
         &yield_vault::ID
     );
+    if !account_exists(user_vault_pda) && account_exists(legacy_pda) {
+        println!("User Vault PDA (legacy): {}", legacy_pda.to_string());
+        return legacy_pda;
+    }
     println!("User Vault PDA: {}", user_vault_pda.to_string());
     user_vault_pda
 }
 
+fn get_new_user_vault_pda(user: Pubkey) -> Pubkey {
+    let (user_vault_pda, _bump) = Pubkey::find_program_address(
+        &[b"vault", user.as_ref(), Pubkey::from_str_const(USDC_MINT).as_ref()],
+        &yield_vault::ID
+    );
+    user_vault_pda
+}
+
+fn account_exists(address: Pubkey) -> bool {
+    RpcClient::new_with_commitment(
+        "http://127.0.0.1:8899".to_string(), CommitmentConfig::confirmed(),
+    ).get_account(&address).is_ok()
+}
+
 fn get_program(kp: Keypair) -> Result<Program<Rc<Keypair>>> {
     Client::new_with_options(
         Cluster::Localnet,
@@ -203,6 +781,11 @@ fn get_user_usdc_ta(user: Pubkey) -> Pubkey {
     user_usdc_ta
 }
 
+// Least acceptable outcome for an operation expected to yield `expected`.
+fn min_out(expected: u64) -> u64 {
+    (expected as u128 * (10_000 - MAX_SLIPPAGE_BPS) as u128 / 10_000) as u64
+}
+
 fn keeper_url() -> String {
     std::env::var("KEEPER_URL").unwrap_or_else(|_| "http://127.0.0.1:8080".to_string())
 }
@@ -5,14 +5,13 @@ use solana_client::rpc_client::RpcClient;
 pub const Program_ID: Pubkey = pubkey!("5urWt3YZS2aXYPhr7LbkQxTHB9o9FDPevV8N1PEeYkYu");
 pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
 pub const KLEND_PROGRAM: &str = "KLend2g3cP87fffoy8q1mQqGKjrxjC8boSyAYavgmjD";
-pub const KLEND_MAIN_LENDING_MARKET: Pubkey = pubkey!("7u3HeHxYDLhnCoErrtycNokbQYbWGzLs6JSDqGAv5PfF");
-pub const KLEND_USDC_RESEVE: Pubkey = pubkey!("D6q6wuQSrifJKZYpR1M8R4YawnLDtDsMmWM1NbBmgJ59");
-pub const KLEND_COLLATERAL_MINT: &str = "B8V6WVjPxW1UGwVDfxH2d2r8SyT4cqn7dQRK6XneVa7D";
-pub const KLEND_RESERVE_LIQUIDITY_SUPPLY: Pubkey = pubkey!("Bgq7trRgVMeq33yt235zM2onQ4bRDBsY5EWiTetF4qw6");
-pub const KLEND_LENDING_MARKET_AUTHORITY: Pubkey = pubkey!("9DrvZvyWh1HuAoZxvYWMvkf2XCzryCpGgHqrMjyDWpmo");
+// klend `refresh_reserve` discriminator, and the placeholder klend stores for unset oracles
+pub const KLEND_REFRESH_RESERVE_DISCRIMINATOR: [u8; 8] = [2, 218, 138, 235, 79, 201, 25, 102];
+pub const KLEND_NULL_PUBKEY: &str = "nu11111111111111111111111111111111111111111";
 
-pub const MARGINFI_GROUP: &str = "4qp6Fx6tnZkY5Wropq9wUYgtFxXKwE6viZxFHg3rdAG8";
 pub const MARGINFI_PROGRAM: &str = "MFv2hWf31Z9kbCa1snEPYctwafyhdvnV7FZnsebVacA";
+// Tolerance applied to expected amounts when building the program's `min_out` guards
+pub const MAX_SLIPPAGE_BPS: u64 = 10;
 pub const RPC_URL: &str = "http://localhost:8899";
 
 
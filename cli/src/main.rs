@@ -25,6 +25,36 @@ enum Command {
     Withdraw {
         keypair_path: std::path::PathBuf,
     },
+    /// Deposit USDC into the pooled vault for vyUSDC shares
+    PoolDeposit {
+        #[arg(short, long)]
+        amount: u64,
+
+        keypair_path: std::path::PathBuf,
+    },
+    /// Burn vyUSDC shares for USDC from the pooled vault
+    PoolWithdraw {
+        #[arg(short, long)]
+        shares: u64,
+
+        keypair_path: std::path::PathBuf,
+    },
+    /// Move a per-user vault into the pooled vault
+    Migrate {
+        keypair_path: std::path::PathBuf,
+    },
+    /// Redeem the user vault from Kamino/Marginfi without the keeper
+    EmergencyRedeem {
+        keypair_path: std::path::PathBuf,
+    },
+    /// Show principal, withdrawals, realized yield and on-chain valuation of the user vault
+    Status {
+        keypair_path: std::path::PathBuf,
+    },
+    /// Close the emptied user vault and reclaim its rent
+    Close {
+        keypair_path: std::path::PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
@@ -43,6 +73,29 @@ fn main() -> Result<()> {
             println!("Withdraw");
             commands::withdraw(keypair_path)?;
         }
+        Command::PoolDeposit { keypair_path, amount } => {
+            println!("Pool Deposit {}", amount);
+            commands::pool_deposit(keypair_path, amount)?;
+        }
+        Command::PoolWithdraw { keypair_path, shares } => {
+            println!("Pool Withdraw {}", shares);
+            commands::pool_withdraw(keypair_path, shares)?;
+        }
+        Command::Migrate { keypair_path } => {
+            println!("Migrate");
+            commands::migrate(keypair_path)?;
+        }
+        Command::EmergencyRedeem { keypair_path } => {
+            println!("Emergency Redeem");
+            commands::emergency_redeem(keypair_path)?;
+        }
+        Command::Status { keypair_path } => {
+            commands::status(keypair_path)?;
+        }
+        Command::Close { keypair_path } => {
+            println!("Close");
+            commands::close(keypair_path)?;
+        }
     
     }
     Ok(())
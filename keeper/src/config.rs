@@ -3,7 +3,7 @@ use anchor_client::solana_sdk::signature::Keypair;
 use crate::rpc::Rpc;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 pub struct Config {
     pub program_id: Pubkey,
@@ -18,13 +18,52 @@ pub enum Strategy {
     Marginfi,
 }
 
+// Target split of a mint's funds between the two protocols, mirrored on-chain in
+// UserVault.kamino_weight_bps; Marginfi gets whatever Kamino does not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weights {
+    pub kamino_bps: u16,
+}
+
+pub const BPS: u16 = 10_000;
+
+impl Weights {
+    pub fn all(strategy: Strategy) -> Self {
+        match strategy {
+            Strategy::Kamino   => Self { kamino_bps: BPS },
+            Strategy::Marginfi => Self { kamino_bps: 0 },
+        }
+    }
+
+    pub fn marginfi_bps(&self) -> u16 {
+        BPS - self.kamino_bps
+    }
+
+    // Protocol holding the larger share (Marginfi on a tie)
+    pub fn dominant(&self) -> Strategy {
+        if self.kamino_bps > self.marginfi_bps() { Strategy::Kamino } else { Strategy::Marginfi }
+    }
+
+    // Splits `amount` into (Kamino, Marginfi) parts
+    pub fn split(&self, amount: u64) -> (u64, u64) {
+        let kamino = (amount as u128 * self.kamino_bps as u128 / BPS as u128) as u64;
+        (kamino, amount - kamino)
+    }
+}
+
+impl std::fmt::Display for Weights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Kamino {}bps / Marginfi {}bps", self.kamino_bps, self.marginfi_bps())
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub program_id: Pubkey,
     pub bot_pubkey: Pubkey,
-    pub strategy: Arc<RwLock<Strategy>>,
+    pub weights: Arc<RwLock<HashMap<Pubkey, Weights>>>, // per mint
     pub rpc: Arc<Rpc>,
-    pub lenders: Arc<RwLock<HashSet<Pubkey>>>,
+    pub lenders: Arc<RwLock<HashSet<(Pubkey, Pubkey)>>>, // (user, mint)
 }
 
 // pub type SharedState = Arc<RwLock<AppState>>;
\ No newline at end of file
@@ -1,15 +1,18 @@
 
 pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
 pub const KLEND_PROGRAM: &str = "KLend2g3cP87fffoy8q1mQqGKjrxjC8boSyAYavgmjD";
-pub const KLEND_MAIN_LENDING_MARKET: &str = "7u3HeHxYDLhnCoErrtycNokbQYbWGzLs6JSDqGAv5PfF";
-pub const KLEND_USDC_RESEVE: &str = "D6q6wuQSrifJKZYpR1M8R4YawnLDtDsMmWM1NbBmgJ59";
-pub const KLEND_COLLATERAL_MINT: &str = "B8V6WVjPxW1UGwVDfxH2d2r8SyT4cqn7dQRK6XneVa7D";
-pub const KLEND_RESERVE_LIQUIDITY_SUPPLY: &str = "Bgq7trRgVMeq33yt235zM2onQ4bRDBsY5EWiTetF4qw6";
-pub const KLEND_LENDING_MARKET_AUTHORITY: &str = "9DrvZvyWh1HuAoZxvYWMvkf2XCzryCpGgHqrMjyDWpmo";
+// klend `refresh_reserve` discriminator, and the placeholder klend stores for unset oracles
+pub const KLEND_REFRESH_RESERVE_DISCRIMINATOR: [u8; 8] = [2, 218, 138, 235, 79, 201, 25, 102];
+pub const KLEND_NULL_PUBKEY: &str = "nu11111111111111111111111111111111111111111";
 
-pub const MARGINFI_BANK: &str = "2s37akK2eyBbp8DZgCm7RtsaEz8eJP3Nxd4urLHQv7yB";
-pub const MARGINFI_GROUP: &str = "4qp6Fx6tnZkY5Wropq9wUYgtFxXKwE6viZxFHg3rdAG8";
 pub const MARGINFI_PROGRAM: &str = "MFv2hWf31Z9kbCa1snEPYctwafyhdvnV7FZnsebVacA";
-pub const MARGINFI_BANK_USDC_LIQUIDITY_VAULT: &str = "7jaiZR5Sk8hdYN9MxTpczTcwbWpb5WEoxSANuUwveuat";
-pub const MARGINFI_BANK_USDC_LIQUIDITY_VAULT_AUTH: &str = "3uxNepDbmkDNq6JhRja5Z8QwbTrfmkKP8AKZV5chYDGG";
+// Mirrors the program's VaultConfig.paused bits
+pub const PAUSE_DEPOSITS: u8 = 1 << 0;
+pub const PAUSE_DEPLOYS: u8 = 1 << 1;
+pub const PAUSE_REBALANCES: u8 = 1 << 2;
+// Tolerance applied to expected amounts when building the program's `min_out` guards
+pub const MAX_SLIPPAGE_BPS: u64 = 10;
+// `migrate_vault` instructions batched into one transaction; each carries the vault's valuation accounts
+pub const MIGRATE_VAULTS_PER_TX: usize = 4;
+
 // pub const RPC_URL: &str = "http://localhost:8899";
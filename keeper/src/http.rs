@@ -1,16 +1,17 @@
 use anyhow::Result;
-use axum::{Json, extract::{State, Path}, routing::{get, post, delete}, Router};
+use axum::{Json, extract::{State, Path, Query}, routing::{get, post, delete}, Router};
 use axum::http::StatusCode;
 use serde::{Serialize, Deserialize};
-use tracing::info;
+use tracing::{info, warn};
 use tokio::net::TcpListener;
 use anchor_lang::prelude::*;
 
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
 use crate::config;
-use crate::rpc::Rpc;
+use crate::markets::MintMarkets;
 
 
 #[derive(Serialize)]
@@ -19,7 +20,18 @@ struct Health {
     service: &'static str,
     bot_kp: String,
     program_id: String,
-    strategy: String,
+    strategy: String,                    // USDC target weights
+    strategies: BTreeMap<String, String>, // target weights per mint, by symbol
+    timelock_delay: Option<i64>,          // seconds between proposing and executing a config change
+    pending_changes: Vec<PendingChangeResp>,
+}
+
+#[derive(Serialize)]
+struct PendingChangeResp {
+    id: u64,
+    change: String,
+    eta: i64,       // unix time from which anyone can execute it
+    executable: bool,
 }
 
 
@@ -27,7 +39,9 @@ struct Health {
 #[derive(Deserialize)]
 struct DepositReq {
     user: String,   // base58 pubkey
-    amount: u64,    // in USDC base units (6 decimals)
+    amount: u64,    // in base units of the mint
+    #[serde(default)]
+    mint: Option<String>, // base58 mint; omit for USDC
 }
 
 #[derive(Serialize)]
@@ -43,6 +57,16 @@ struct DepositResp {
 #[derive(Deserialize)]
 struct WithdrawReq {
     user: String,
+    #[serde(default)]
+    amount: Option<u64>, // base units of the mint; omit to unwind everything
+    #[serde(default)]
+    mint: Option<String>, // base58 mint; omit for USDC
+}
+
+// `?mint=<base58>` on GET/DELETE routes; omit for USDC
+#[derive(Deserialize)]
+struct MintQuery {
+    mint: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -52,47 +76,101 @@ struct WithdrawResp {
     user: String,
 }
 
+#[derive(Serialize)]
+struct VaultResp {
+    user: String,
+    vault: String,
+    active_protocol: String,
+    principal: u64,      // deposited minus principal withdrawn
+    withdrawn: u64,      // cumulative USDC withdrawn
+    realized_yield: u64, // fee-assessed yield still in the vault
+    idle: u64,           // USDC sitting in the vault ATA
+    kamino: u64,         // Kamino position at the reserve exchange rate
+    marginfi: u64,       // Marginfi position at the bank share value
+    total: u64,          // idle + kamino + marginfi, valued on-chain by get_position
+    kamino_weight_bps: u16,
+}
+
+#[derive(Serialize)]
+struct HeadroomResp {
+    user: String,
+    vault: Option<u64>,     // remaining per-vault deposit room, null = no cap
+    global: Option<u64>,    // remaining program-wide deposit room, null = no cap
+    available: Option<u64>, // what the user can still deposit, null = no cap
+}
+
+#[derive(Serialize)]
+struct MigrateVaultsResp {
+    ok: bool,
+    migrated: usize,  // vaults upgraded to the current layout
+    txs: Vec<String>,
+}
+
+
+// Registry entry for a request's `mint`; the first configured market (USDC) when omitted.
+fn market<'a>(st: &'a config::AppState, mint: Option<&str>) -> Result<&'a MintMarkets, (StatusCode, String)> {
+    let Some(mint) = mint else {
+        return Ok(&st.rpc.markets[0]);
+    };
+    let mint: Pubkey = mint.parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid mint: {e}")))?;
+    st.rpc.market(&mint).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
 
 async fn health(State(st): State<config::AppState>) -> Json<Health> {
     info!("Health Check");
 
     let state_clone = st.clone();
-    let strat = state_clone.strategy.read().await;
-    match *strat {
-        config::Strategy::Kamino   => {
-            Json(Health {
-                ok: true,
-                service: "keeper_kamino",
-                bot_kp: st.bot_pubkey.to_string(),
-                program_id: st.program_id.to_string(),
-                strategy: format!("{:?}", *strat),
-            })
-        },
-
-       config::Strategy::Marginfi =>  {
-        Json(Health {
-            ok: true,
-            service: "keeper_marginfi",
-            bot_kp: st.bot_pubkey.to_string(),
-            program_id: st.program_id.to_string(),
-            strategy: format!("{:?}", *strat),
-        })
-       }
-    }
+    let all_weights = state_clone.weights.read().await;
+    let by_symbol: BTreeMap<String, String> = st.rpc.markets.iter()
+        .filter_map(|m| all_weights.get(&m.mint).map(|w| (m.symbol.clone(), w.to_string())))
+        .collect();
+    let weights = all_weights.get(&st.rpc.markets[0].mint).copied().unwrap_or(config::Weights::all(config::Strategy::Marginfi));
+    // Named after the protocol holding most of the USDC funds
+    let service = match weights.dominant() {
+        config::Strategy::Kamino   => "keeper_kamino",
+        config::Strategy::Marginfi => "keeper_marginfi",
+    };
+    // Timelocked admin changes to the program config, so operators see them coming
+    let (timelock_delay, pending_changes) = match tokio::task::block_in_place(|| st.rpc.pending_config_changes()) {
+        Ok((delay, pending)) => {
+            let now = chrono::Utc::now().timestamp();
+            let pending = pending.into_iter()
+                .map(|p| PendingChangeResp { id: p.id, change: format!("{:?}", p.change), eta: p.eta, executable: now >= p.eta })
+                .collect();
+            (Some(delay), pending)
+        }
+        Err(e) => {
+            warn!(error=?e, "reading pending config changes failed");
+            (None, vec![])
+        }
+    };
+    Json(Health {
+        ok: true,
+        service,
+        bot_kp: st.bot_pubkey.to_string(),
+        program_id: st.program_id.to_string(),
+        strategy: weights.to_string(),
+        strategies: by_symbol,
+        timelock_delay,
+        pending_changes,
+    })
 }
 
 async fn delete_lender(
     State(st): State<config::AppState>,
     Path(user_str): Path<String>,
+    Query(q): Query<MintQuery>,
 ) -> Result<StatusCode, (StatusCode, String)> {
     // parse user pubkey
     let user: Pubkey = user_str.parse()
         .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid user pubkey: {e}")))?;
+    let m = market(&st, q.mint.as_deref())?;
 
     // idempotent remove
     {
         let mut set = st.lenders.write().await;
-        set.remove(&user);
+        set.remove(&(user, m.mint));
     }
 
     Ok(StatusCode::NO_CONTENT)
@@ -102,21 +180,104 @@ async fn withdraw(
     State(st): State<config::AppState>, 
     Json(req): Json<WithdrawReq>) -> Result<Json<WithdrawResp>, (StatusCode, String)>  {
 
-    let strat = st.strategy.read().await;
     let user: Pubkey = req.user.parse()
         .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid user pubkey: {e}")))?;
+    let m = market(&st, req.mint.as_deref())?;
     info!("Making Withdraw RPC call..");
-    let sig = tokio::task::block_in_place(|| {
-        match *strat {
-            config::Strategy::Kamino   => st.rpc.withdraw_from_kamino(user),
-            config::Strategy::Marginfi => st.rpc.withdraw_from_marginfi(user),
-        }
+    // The vault's on-chain active_protocol decides what to redeem from
+    if req.amount == Some(0) {
+        return Err((StatusCode::BAD_REQUEST, "amount must be > 0".into()));
+    }
+    let sig = tokio::task::block_in_place(|| crate::tracker::unwind_user(&st, m, &user, req.amount))
+    .map_err(|e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .unwrap_or_default();
+
+    Ok(Json(WithdrawResp { ok: true, tx: sig, user: user.to_string() }))
+}
+
+// GET /vault/:pubkey: on-chain accounting and valuation of a user vault.
+async fn vault(
+    State(st): State<config::AppState>,
+    Path(user_str): Path<String>,
+    Query(q): Query<MintQuery>,
+) -> Result<Json<VaultResp>, (StatusCode, String)> {
+    let user: Pubkey = user_str.parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid user pubkey: {e}")))?;
+    let m = market(&st, q.mint.as_deref())?;
+    let (vault_pda, vault, position) = tokio::task::block_in_place(|| {
+        let vault_pda = st.rpc.vault_address(&user, &m.mint);
+        let vault = st.rpc.user_vault(m, &user)?;
+        let position = st.rpc.position(m, &user)?;
+        Ok((vault_pda, vault, position))
     })
     .map_err(|e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
+    Ok(Json(VaultResp {
+        user: user.to_string(),
+        vault: vault_pda.to_string(),
+        active_protocol: format!("{:?}", vault.active_protocol),
+        principal: vault.deposited_amount,
+        withdrawn: vault.withdrawn_amount,
+        realized_yield: vault.realized_yield,
+        idle: position.idle,
+        kamino: position.kamino,
+        marginfi: position.marginfi,
+        total: position.total,
+        kamino_weight_bps: vault.kamino_weight_bps,
+    }))
+}
+
+// GET /headroom/:pubkey: how much more the user can deposit under the on-chain caps.
+async fn headroom(
+    State(st): State<config::AppState>,
+    Path(user_str): Path<String>,
+    Query(q): Query<MintQuery>,
+) -> Result<Json<HeadroomResp>, (StatusCode, String)> {
+    let user: Pubkey = user_str.parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid user pubkey: {e}")))?;
+    let m = market(&st, q.mint.as_deref())?;
+    let (vault, global) = tokio::task::block_in_place(|| st.rpc.deposit_headroom(m, &user))
+    .map_err(|e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let available = match (vault, global) {
+        (Some(v), Some(g)) => Some(v.min(g)),
+        (v, g) => v.or(g),
+    };
+
+    Ok(Json(HeadroomResp { user: user.to_string(), vault, global, available }))
+}
+
+// POST /migrate: grow a legacy UserVault to the current layout.
+// Tracked lenders are deployed to the protocol with the larger current weight, everyone else is idle.
+async fn migrate(
+    State(st): State<config::AppState>, 
+    Json(req): Json<WithdrawReq>) -> Result<Json<WithdrawResp>, (StatusCode, String)>  {
+    let user: Pubkey = req.user.parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid user pubkey: {e}")))?;
+    let m = market(&st, req.mint.as_deref())?;
+    let deployed_to = if st.lenders.read().await.contains(&(user, m.mint)) {
+        st.weights.read().await.get(&m.mint).map(|w| w.dominant())
+    } else {
+        None
+    };
+    info!("Making Migrate RPC call..");
+    let sig = tokio::task::block_in_place(|| st.rpc.migrate_user_vault(m, user, deployed_to))
+    .map_err(|e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
     Ok(Json(WithdrawResp { ok: true, tx: sig, user: user.to_string() }))
 }
 
+// POST /migrate/vaults: upgrade every vault still on the baseline layout in batched `migrate_vault` transactions.
+async fn migrate_vaults(State(st): State<config::AppState>) -> Result<Json<MigrateVaultsResp>, (StatusCode, String)> {
+    info!("Migrating outdated vault layouts..");
+    let (migrated, txs) = tokio::task::block_in_place(|| {
+        let vaults = st.rpc.outdated_vaults()?;
+        Ok((vaults.len(), st.rpc.migrate_vaults(&vaults)?))
+    })
+    .map_err(|e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(MigrateVaultsResp { ok: true, migrated, txs }))
+}
+
 // POST /deposit handler
 async fn deposit(
     State(st): State<config::AppState>, 
@@ -127,29 +288,27 @@ async fn deposit(
     }
     let user: Pubkey = req.user.parse()
         .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid user pubkey: {e}")))?;
+    let m = market(&st, req.mint.as_deref())?;
     
     info!("Making Deposit RPC call..");
-    let strat = st.strategy.read().await;
-    // http.rs (inside POST /deposit handler)
-    let sig = tokio::task::block_in_place(|| {
-        match *strat {
-            config::Strategy::Kamino   => st.rpc.deposit_to_kamino(user, req.amount),
-            config::Strategy::Marginfi => st.rpc.deposit_to_marginfi(user, req.amount),
-        }
+    let weights = st.weights.read().await.get(&m.mint).copied().unwrap_or(config::Weights::all(config::Strategy::Marginfi));
+    // The deposit sits idle in the vault ATA; allocating spreads it (and any other idle USDC) per the weights
+    let (sig, vault) = tokio::task::block_in_place(|| {
+        let sig = st.rpc.allocate(m, user, weights)?;
+        Ok((sig, st.rpc.vault_address(&user, &m.mint)))
     })
     .map_err(|e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let (vault, _) = crate::rpc::Rpc::vault_pda(&user);
     {
         let mut set = st.lenders.write().await;
-        set.insert(user);
+        set.insert((user, m.mint));
     }
     Ok(Json(DepositResp {
         ok: true,
         tx: sig,
         user: user.to_string(),
         vault: vault.to_string(),
-        protocol: format!("{:?}", *strat),
+        protocol: weights.to_string(),
         requested: req.amount,
     }))
 }
@@ -162,6 +321,10 @@ pub async fn run_http(
     .route("/health", get(health))
     .route("/deposit", post(deposit))
     .route("/withdraw", post(withdraw))
+    .route("/migrate", post(migrate))
+    .route("/migrate/vaults", post(migrate_vaults))
+    .route("/headroom/:pubkey", get(headroom))
+    .route("/vault/:pubkey", get(vault))
     .route("/lenders/:pubkey", delete(delete_lender))
     .with_state(app_state);
     
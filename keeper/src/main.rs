@@ -1,4 +1,4 @@
-use std::{sync::Arc, collections::HashSet};
+use std::{sync::Arc, collections::{HashMap, HashSet}};
 use anyhow::{Result, anyhow};
 use anchor_lang::prelude::*;
 use anchor_client::solana_sdk::{
@@ -17,6 +17,7 @@ mod consts;
 mod rpc;
 mod tracker;
 mod marginfi_apy;
+mod markets;
 
 
 
@@ -32,18 +33,41 @@ async fn main() -> Result<()> {
     let bot_kp = get_kp()?;
     let bot_pubkey = bot_kp.pubkey();
 
+    let mut rpc = rpc::Rpc::new(bot_kp)?;
+
+    // 0) Make sure the program config exists, then load the per-mint markets from the on-chain registry
+    if let Err(e) = tokio::task::block_in_place(|| rpc.ensure_config()) {
+        tracing::warn!(error=?e, "program config bootstrap failed");
+    }
+    tokio::task::block_in_place(|| rpc.load_markets())?;
+    let weights: HashMap<Pubkey, config::Weights> = rpc.markets.iter()
+        .map(|m| (m.mint, config::Weights::all(config::Strategy::Marginfi)))
+        .collect();
     let config = Box::leak(Box::new(config::AppState {
         program_id: yield_vault::ID,
         bot_pubkey: bot_pubkey,
-        strategy: Arc::new(tokio::sync::RwLock::new(config::Strategy::Marginfi)),
-        rpc:  Arc::new(rpc::Rpc::new(bot_kp)?),
+        weights: Arc::new(tokio::sync::RwLock::new(weights)),
+        rpc:  Arc::new(rpc),
         lenders: Arc::new(tokio::sync::RwLock::new(HashSet::new())), 
     }));
 
-    tracing::info!(%bot_pubkey, program_id = %yield_vault::ID, "Keeper starting up");
+    tracing::info!(%bot_pubkey, program_id = %yield_vault::ID, mints = config.rpc.markets.len(), "Keeper starting up");
+
 
+    // Make sure the pooled vaults exist before the tracker starts moving funds
+    for m in &config.rpc.markets {
+        if let Err(e) = tokio::task::block_in_place(|| config.rpc.ensure_pool(m)) {
+            tracing::warn!(symbol = %m.symbol, error=?e, "pooled vault bootstrap failed");
+        }
+    }
+    // Vaults on the baseline layout can't be read by the program until they are migrated
+    match tokio::task::block_in_place(|| config.rpc.outdated_vaults().and_then(|v| config.rpc.migrate_vaults(&v))) {
+        Ok(txs) if !txs.is_empty() => tracing::info!(txs = txs.len(), "vault layouts migrated"),
+        Ok(_) => {}
+        Err(e) => tracing::warn!(error=?e, "vault layout migration failed"),
+    }
 
-    // 1) One-shot: compute APYs and set initial strategy at startup
+    // 1) One-shot: compute APYs and set initial weights at startup
     tracker::bootstrap_once(config.clone()).await;
 
     // 2) Background: hourly tracker loop
@@ -0,0 +1,83 @@
+// Per-mint protocol markets: the Kamino reserve and Marginfi bank each mint's vaults deploy to.
+// They are loaded from the program's ProtocolRegistry (`[b"registry"]`), which the admin manages
+// through timelocked SetMarkets / RemoveMarkets config changes, so nothing here is compiled in.
+// Each mint's token program is read from the mint account (SPL Token or Token-2022, e.g. PYUSD),
+// and the Kamino market authority and Marginfi liquidity vault authority are derived PDAs.
+use anyhow::{anyhow, Context, Result};
+use anchor_client::{Program, solana_sdk::signature::Keypair};
+use anchor_lang::prelude::*;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use std::sync::Arc;
+
+use crate::consts::*;
+use crate::yield_vault::{accounts::ProtocolRegistry, types::RegistryEntry};
+
+#[derive(Debug, Clone)]
+pub struct MintMarkets {
+    pub symbol: String,
+    pub mint: Pubkey,
+    pub token_program: Pubkey, // owner of `mint`: SPL Token or Token-2022
+    // Kamino
+    pub klend_market: Pubkey,
+    pub klend_market_auth: Pubkey,
+    pub klend_reserve: Pubkey,
+    pub klend_reserve_liq_supply: Pubkey,
+    pub klend_collateral_mint: Pubkey,
+    // Marginfi
+    pub mfi_group: Pubkey,
+    pub mfi_bank: Pubkey,
+    pub mfi_bank_liq_vault: Pubkey,
+    pub mfi_bank_liq_vault_auth: Pubkey,
+}
+
+impl MintMarkets {
+    fn from_entry(entry: &RegistryEntry, token_program: Pubkey) -> Self {
+        let (klend_market_auth, _) = Pubkey::find_program_address(
+            &[b"lma", entry.kamino_lending_market.as_ref()],
+            &Pubkey::from_str_const(KLEND_PROGRAM),
+        );
+        let (mfi_bank_liq_vault_auth, _) = Pubkey::find_program_address(
+            &[b"liquidity_vault_auth", entry.marginfi_bank.as_ref()],
+            &Pubkey::from_str_const(MARGINFI_PROGRAM),
+        );
+        // The registry holds no names; mints other than USDC are labelled by address
+        let symbol = if entry.mint == Pubkey::from_str_const(USDC_MINT) { "USDC".to_string() } else { entry.mint.to_string() };
+        Self {
+            symbol,
+            mint: entry.mint,
+            token_program,
+            klend_market: entry.kamino_lending_market,
+            klend_market_auth,
+            klend_reserve: entry.kamino_reserve,
+            klend_reserve_liq_supply: entry.kamino_liquidity_supply,
+            klend_collateral_mint: entry.kamino_collateral_mint,
+            mfi_group: entry.marginfi_group,
+            mfi_bank: entry.marginfi_bank,
+            mfi_bank_liq_vault: entry.marginfi_liquidity_vault,
+            mfi_bank_liq_vault_auth,
+        }
+    }
+
+    // Associated token account of `owner` for this mint, under the mint's token program.
+    pub fn ata(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(owner, &self.mint, &self.token_program)
+    }
+}
+
+// USDC first (the default mint for HTTP requests without `mint`), then the other registered mints.
+pub fn load(program: &Program<Arc<Keypair>>, registry: Pubkey) -> Result<Vec<MintMarkets>> {
+    let registry: ProtocolRegistry = program.account(registry)
+        .with_context(|| format!("reading the protocol registry {}", registry))?;
+    let mut markets = Vec::with_capacity(registry.entries.len());
+    for entry in &registry.entries {
+        let mint = program.rpc().get_account(&entry.mint)
+            .with_context(|| format!("reading mint {}", entry.mint))?;
+        markets.push(MintMarkets::from_entry(entry, mint.owner));
+    }
+    if markets.is_empty() {
+        return Err(anyhow!("the protocol registry has no markets; register them with a SetMarkets config change"));
+    }
+    let usdc = Pubkey::from_str_const(USDC_MINT);
+    markets.sort_by_key(|m| m.mint != usdc);
+    Ok(markets)
+}
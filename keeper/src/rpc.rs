@@ -3,37 +3,33 @@ use anyhow::{anyhow, Result};
 use std::sync::Arc;
 
 use anchor_client::{
+    solana_client::{rpc_config::RpcProgramAccountsConfig, rpc_filter::{Memcmp, RpcFilterType}},
     solana_sdk::{
-     commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Keypair, signer::Signer, system_program, sysvar
+     commitment_config::CommitmentConfig, instruction::{AccountMeta, Instruction}, pubkey::Pubkey, signature::Keypair, signer::Signer, system_program, sysvar
     }, Client, Cluster, Program
 };
 use anchor_lang::{prelude::*};
+use kamino_lend::state::Reserve;
 use tracing::info;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use spl_associated_token_account::get_associated_token_address;
 
-use crate::{consts::*, yield_vault::accounts::UserVault};
+use crate::{consts::*, config::{Strategy, Weights}, markets::{self, MintMarkets}, yield_vault::accounts::{UserVault, VaultConfig, VaultState}, yield_vault::types::{PendingChange, Position}};
 declare_program!(yield_vault);
 use yield_vault::{client::accounts, client::args};
 
 // Convenient wrapper holding long-lived RPC + Anchor Program
 pub struct Rpc {
     pub program: Program<Arc<Keypair>>,
-    // parsed ids (from consts.rs)
+    // parsed ids (from consts.rs), shared by every mint
     // Kamino
-    pub usdc_mint: Pubkey,
     pub klend_program: Pubkey,
-    pub klend_market: Pubkey,
-    pub klend_market_auth: Pubkey,
-    pub klend_reserve: Pubkey,
-    pub klend_reserve_liq_supply: Pubkey,
-    pub klend_collateral_mint: Pubkey,
 
     // Marginfi
     pub mfi_program: Pubkey,
-    pub mfi_group: Pubkey,
-    pub mfi_bank: Pubkey,
-    pub mfi_bank_liq_vault: Pubkey,
-    pub mfi_bank_liq_vault_auth: Pubkey,
+
+    // Per-mint markets from the on-chain ProtocolRegistry (see `load_markets`); the first entry is USDC
+    pub markets: Vec<MintMarkets>,
 
     // Bot(Keeper) credentials
     pub bot_pubkey: Pubkey,
@@ -50,43 +46,65 @@ impl Rpc {
             CommitmentConfig::confirmed()
         ).program(yield_vault::ID)?;
 
-        let usdc_mint = Pubkey::from_str_const(USDC_MINT);
         let klend_program = Pubkey::from_str_const(KLEND_PROGRAM);
-        let klend_market = Pubkey::from_str_const(KLEND_MAIN_LENDING_MARKET);
-        let klend_market_auth = Pubkey::from_str_const(KLEND_LENDING_MARKET_AUTHORITY);
-        let klend_reserve = Pubkey::from_str_const(KLEND_USDC_RESEVE);
-        let klend_reserve_liq_supply = Pubkey::from_str_const(KLEND_RESERVE_LIQUIDITY_SUPPLY);
-        let klend_collateral_mint = Pubkey::from_str_const(KLEND_COLLATERAL_MINT);
-        
         let mfi_program = Pubkey::from_str_const(MARGINFI_PROGRAM);
-        let mfi_group = Pubkey::from_str_const(MARGINFI_GROUP);
-        let mfi_bank = Pubkey::from_str_const(MARGINFI_BANK);
-        let mfi_bank_liq_vault = Pubkey::from_str_const(MARGINFI_BANK_USDC_LIQUIDITY_VAULT);
-        let mfi_bank_liq_vault_auth = Pubkey::from_str_const(MARGINFI_BANK_USDC_LIQUIDITY_VAULT_AUTH);
-
 
         Ok(Self { 
             program,
-            usdc_mint,
             klend_program,
-            klend_market,
-            klend_market_auth,
-            klend_reserve,
-            klend_reserve_liq_supply,
-            klend_collateral_mint,
             mfi_program,
-            mfi_group,
-            mfi_bank,
-            mfi_bank_liq_vault,
-            mfi_bank_liq_vault_auth,
+            markets: vec![],
             bot_pubkey,
             bot_kp,
         })
     }
-    pub fn vault_pda(user: &Pubkey) -> (Pubkey, u8) {
+    pub fn vault_pda(user: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"vault", user.as_ref(), mint.as_ref()], &yield_vault::ID)
+    }
+
+    // Address of vaults created before multi-mint support (USDC only)
+    pub fn legacy_vault_pda(user: &Pubkey) -> (Pubkey, u8) {
         Pubkey::find_program_address(&[b"vault", user.as_ref()], &yield_vault::ID)
     }
 
+    // The user's vault for `mint`: the (owner, mint) PDA, or the legacy address for a USDC vault
+    // that was created before multi-mint support and has no mint-keyed successor.
+    pub fn vault_address(&self, user: &Pubkey, mint: &Pubkey) -> Pubkey {
+        let (vault_pda, _) = Self::vault_pda(user, mint);
+        if *mint != Pubkey::from_str_const(USDC_MINT) || self.program.rpc().get_account(&vault_pda).is_ok() {
+            return vault_pda;
+        }
+        let (legacy_pda, _) = Self::legacy_vault_pda(user);
+        if self.program.rpc().get_account(&legacy_pda).is_ok() { legacy_pda } else { vault_pda }
+    }
+
+    // Replaces `markets` with the ProtocolRegistry's current entries.
+    pub fn load_markets(&mut self) -> Result<()> {
+        self.markets = markets::load(&self.program, Self::registry_pda().0)?;
+        Ok(())
+    }
+
+    // Registry entry for `mint`; fails for mints the keeper has no markets for.
+    pub fn market(&self, mint: &Pubkey) -> Result<&MintMarkets> {
+        self.markets.iter().find(|m| m.mint == *mint).ok_or_else(|| anyhow!("no markets configured for mint {}", mint))
+    }
+
+    pub fn vault_state_pda(mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"vault_state", mint.as_ref()], &yield_vault::ID)
+    }
+
+    pub fn share_mint_pda(vault_state: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"share_mint", vault_state.as_ref()], &yield_vault::ID)
+    }
+
+    pub fn config_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"config"], &yield_vault::ID)
+    }
+
+    pub fn registry_pda() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"registry"], &yield_vault::ID)
+    }
+
     pub fn ata(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
         get_associated_token_address(owner, mint)
     }
@@ -100,33 +118,259 @@ impl Rpc {
         Ok(amount_u64)
     }
 
-    pub fn withdraw_from_marginfi(&self, user: Pubkey) -> Result<String> {
-        info!(%user, "withdrawing from marginfi for");
-        let (vault_pda, _bump) = Self::vault_pda(&user);
-        let vault_usdc_ata = Self::ata(&vault_pda, &self.usdc_mint);
+    // klend `refresh_reserve` for the mint's reserve. Kamino only deposits to / redeems from a reserve
+    // refreshed in the same slot, and the program requires this instruction ahead of every Kamino CPI,
+    // so it is prepended to each transaction that may touch Kamino. The oracles come from the reserve's
+    // config; unset ones are passed as the klend program id, Anchor's encoding of a missing account.
+    pub fn refresh_reserve_ix(&self, m: &MintMarkets) -> Result<Instruction> {
+        let account = self.program.rpc().get_account(&m.klend_reserve)?;
+        let data = account.data.get(8..8 + std::mem::size_of::<Reserve>())
+            .ok_or_else(|| anyhow!("reserve {} has an unexpected size", m.klend_reserve))?;
+        let reserve: Reserve = bytemuck::pod_read_unaligned(data);
+        let token_info = &reserve.config.token_info;
+        let oracle = |key: Pubkey| {
+            let unset = key == Pubkey::default() || key == Pubkey::from_str_const(KLEND_NULL_PUBKEY);
+            AccountMeta::new_readonly(if unset { self.klend_program } else { key }, false)
+        };
+        Ok(Instruction {
+            program_id: self.klend_program,
+            accounts: vec![
+                AccountMeta::new(m.klend_reserve, false),
+                AccountMeta::new_readonly(m.klend_market, false),
+                oracle(token_info.pyth_configuration.price),
+                oracle(token_info.switchboard_configuration.price_aggregator),
+                oracle(token_info.switchboard_configuration.twap_aggregator),
+                oracle(token_info.scope_configuration.price_feed),
+            ],
+            data: KLEND_REFRESH_RESERVE_DISCRIMINATOR.to_vec(),
+        })
+    }
+
+    pub fn user_vault(&self, m: &MintMarkets, user: &Pubkey) -> Result<UserVault> {
+        let vault_pda = self.vault_address(user, &m.mint);
+        let vault: UserVault = self.program.account(vault_pda)?;
+        Ok(vault)
+    }
+
+    // Values the vault on-chain by simulating `get_position` and decoding its return data.
+    pub fn position(&self, m: &MintMarkets, user: &Pubkey) -> Result<Position> {
+        let vault_pda = self.vault_address(user, &m.mint);
+        let vault: UserVault = self.program.account(vault_pda)?;
+        let accounts = accounts::GetPosition {
+            user: *user,
+            usdc_mint: m.mint,
+            user_vault_account: vault_pda,
+            user_usdc_vault_ata: m.ata(&vault_pda),
+            kamino_reserve: m.klend_reserve,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            kamino_usdc_collateral_vault: Self::ata(&vault_pda, &m.klend_collateral_mint),
+            marginfi_account: vault.marginfi_account,
+            marginfi_bank: m.mfi_bank,
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
+        };
+        let tx = self.program.request().accounts(accounts).args(args::GetPosition).signed_transaction()?;
+        let sim = self.program.rpc().simulate_transaction(&tx)?.value;
+        if let Some(err) = sim.err {
+            return Err(anyhow!("get_position simulation failed: {:?}, logs: {:?}", err, sim.logs));
+        }
+        let return_data = sim.return_data.ok_or_else(|| anyhow!("get_position returned no data"))?;
+        let bytes = BASE64.decode(&return_data.data.0)?;
+        Ok(Position::try_from_slice(&bytes)?)
+    }
+
+    // Grows a legacy UserVault to the current layout, recording where its funds sit.
+    // Legacy vaults always live at the pre-multi-mint address. The program resets the vault's principal
+    // to what it holds, so the reserve is refreshed first to value its kUSDC at the current rate.
+    pub fn migrate_user_vault(&self, m: &MintMarkets, user: Pubkey, deployed_to: Option<Strategy>) -> Result<String> {
+        info!(%user, symbol = %m.symbol, ?deployed_to, "migrating vault layout for");
+        let (vault_pda, _bump) = Self::legacy_vault_pda(&user);
+        let data = self.program.rpc().get_account_data(&vault_pda)?;
+        let marginfi_account = baseline_vault(&data)
+            .ok_or_else(|| anyhow!("vault {} is not on the baseline layout", vault_pda))?
+            .marginfi_account;
+        let active_protocol = deployed_to.map(protocol_of).unwrap_or(yield_vault::types::Protocol::None);
+        let accounts = accounts::MigrateUserVault {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            user,
+            user_vault_account: vault_pda,
+            usdc_mint: m.mint,
+            user_usdc_vault_ata: m.ata(&vault_pda),
+            kamino_reserve: m.klend_reserve,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            kamino_usdc_collateral_vault: Self::ata(&vault_pda, &m.klend_collateral_mint),
+            marginfi_account,
+            marginfi_bank: m.mfi_bank,
+            system_program: system_program::ID,
+        };
+        let tx = self.program.request().accounts(accounts).args(args::MigrateUserVault { active_protocol }).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(self.refresh_reserve_ix(m)?)
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+
+    // User vaults still on the baseline layout, i.e. that don't decode as the current UserVault.
+    pub fn outdated_vaults(&self) -> Result<Vec<(Pubkey, BaselineUserVault)>> {
+        let filters = vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, UserVault::DISCRIMINATOR.to_vec()))];
+        let config = RpcProgramAccountsConfig { filters: Some(filters), ..Default::default() };
+        let accounts = self.program.rpc().get_program_accounts_with_config(&yield_vault::ID, config)?;
+        Ok(accounts.into_iter()
+            .filter_map(|(vault, account)| baseline_vault(&account.data).map(|baseline| (vault, baseline)))
+            .collect())
+    }
+
+    // Upgrades baseline `vaults` to the current layout, MIGRATE_VAULTS_PER_TX `migrate_vault` instructions per
+    // transaction. Baseline vaults all hold USDC; the reserve is refreshed first, as the program sets each vault's
+    // protocol and principal from what it holds.
+    pub fn migrate_vaults(&self, vaults: &[(Pubkey, BaselineUserVault)]) -> Result<Vec<String>> {
+        let m = self.market(&Pubkey::from_str_const(USDC_MINT))?;
+        let mut signatures = Vec::with_capacity(vaults.len().div_ceil(MIGRATE_VAULTS_PER_TX));
+        for chunk in vaults.chunks(MIGRATE_VAULTS_PER_TX) {
+            let mut request = self.program.request().instruction(self.refresh_reserve_ix(m)?);
+            for (vault, baseline) in chunk {
+                let accounts = accounts::MigrateVault {
+                    keeper: self.bot_pubkey,
+                    config: Self::config_pda().0,
+                    registry: Self::registry_pda().0,
+                    user_vault_account: *vault,
+                    usdc_mint: m.mint,
+                    user_usdc_vault_ata: m.ata(vault),
+                    kamino_reserve: m.klend_reserve,
+                    kamino_usdc_collateral_mint: m.klend_collateral_mint,
+                    kamino_usdc_collateral_vault: Self::ata(vault, &m.klend_collateral_mint),
+                    marginfi_account: baseline.marginfi_account,
+                    marginfi_bank: m.mfi_bank,
+                    system_program: system_program::ID,
+                    token_program: m.token_program,
+                    collateral_token_program: spl_token::id(),
+                };
+                let ix = self.program.request().accounts(accounts).args(args::MigrateVault).instructions()?.remove(0);
+                request = request.instruction(ix);
+            }
+            let signature = request.signer(self.bot_kp.insecure_clone()).send()?;
+            info!(%signature, vaults = chunk.len(), "migrated vault layouts");
+            signatures.push(signature.to_string());
+        }
+        Ok(signatures)
+    }
+
+    // Redeems everything from `from` and deposits the proceeds into `to` in one instruction.
+    pub fn rebalance(&self, m: &MintMarkets, user: Pubkey, from: Strategy, to: Strategy) -> Result<String> {
+        info!(%user, symbol = %m.symbol, ?from, ?to, "rebalancing vault of");
+        // The whole position in `from` moves; idle USDC stays where it is
+        let position = self.position(m, &user)?;
+        let expected = position.kamino + position.marginfi;
+        let tx = self.program
+            .request()
+            .accounts(self.rebalance_accounts(m, user)?)
+            .args(args::Rebalance { from: protocol_of(from), to: protocol_of(to), min_out: min_out(expected) })
+            .instructions()?
+            .remove(0);
+        let signature = self.program
+            .request()
+            .instruction(self.refresh_reserve_ix(m)?)
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+
+    // Moves only the difference needed to reach `weights`, deploying idle vault USDC along the way.
+    pub fn allocate(&self, m: &MintMarkets, user: Pubkey, weights: Weights) -> Result<String> {
+        info!(%user, symbol = %m.symbol, %weights, "allocating vault of");
+        // Same accounts as `rebalance`
+        let tx = self.program
+            .request()
+            .accounts(self.rebalance_accounts(m, user)?)
+            .args(args::Allocate { kamino_weight_bps: weights.kamino_bps, min_out: min_out(self.position(m, &user)?.total) })
+            .instructions()?
+            .remove(0);
+        let signature = self.program
+            .request()
+            .instruction(self.refresh_reserve_ix(m)?)
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+
+    fn rebalance_accounts(&self, m: &MintMarkets, user: Pubkey) -> Result<accounts::Rebalance> {
+        let vault_pda = self.vault_address(&user, &m.mint);
+        let vault_pda_acc: UserVault = self.program.account(vault_pda)?;
+        Ok(accounts::Rebalance {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            treasury_usdc_ta: self.treasury_ta(m)?,
+            usdc_mint: m.mint,
+            user,
+            user_vault_account: vault_pda,
+            user_usdc_vault_ata: m.ata(&vault_pda),
+            // Kamino
+            kamino_program: self.klend_program,
+            kamino_lending_market: m.klend_market,
+            kamino_lending_market_authority: m.klend_market_auth,
+            kamino_reserve: m.klend_reserve,
+            kamino_reserve_liquidity_supply: m.klend_reserve_liq_supply,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            kamino_usdc_collateral_vault: Self::ata(&vault_pda, &m.klend_collateral_mint),
+            // Marginfi
+            marginfi_group: m.mfi_group,
+            marginfi_account: vault_pda_acc.marginfi_account,
+            marginfi_bank: m.mfi_bank,
+            marginfi_bank_liquidity_vault_authority: m.mfi_bank_liq_vault_auth,
+            marginfi_bank_liquidity_vault: m.mfi_bank_liq_vault,
+            marginfi_program: self.mfi_program,
+            // Built-ins
+            associated_token_program: spl_associated_token_account::id(),
+            system_program: system_program::ID,
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
+            instruction_sysvar_account: sysvar::instructions::ID,
+        })
+    }
+
+    // `amount` = None closes the whole Marginfi balance, Some(amount) withdraws only that much USDC.
+    pub fn withdraw_from_marginfi(&self, m: &MintMarkets, user: Pubkey, amount: Option<u64>) -> Result<String> {
+        info!(%user, symbol = %m.symbol, ?amount, "withdrawing from marginfi for");
+        if amount == Some(0) {
+            return Err(anyhow!("amount must be > 0"));
+        }
+        let vault_pda = self.vault_address(&user, &m.mint);
+        let vault_usdc_ata = m.ata(&vault_pda);
 
         let vault_pda_acc: UserVault = self.program.account(vault_pda)?;
         let marginfi_account = vault_pda_acc.marginfi_account;
         info!(%marginfi_account, %vault_pda, "marginfi account from vault");
         let accounts = accounts::RedeemUsdcMarginfi{
             keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            treasury_usdc_ta: self.treasury_ta(m)?,
             user: user,
-            usdc_mint: self.usdc_mint,
+            usdc_mint: m.mint,
             user_vault_account: vault_pda,
             user_usdc_vault_ata: vault_usdc_ata,
             // marginfi CPI accounts
             marginfi_program: self.mfi_program,
-            marginfi_group: self.mfi_group,
+            marginfi_group: m.mfi_group,
             marginfi_account: vault_pda_acc.marginfi_account, // or the stored marginfi_account pubkey from your vault
-            marginfi_bank: self.mfi_bank,
-            marginfi_bank_liquidity_vault_authority: self.mfi_bank_liq_vault_auth,
-            marginfi_bank_liquidity_vault: self.mfi_bank_liq_vault,
-            token_program: spl_token::id(),
+            marginfi_bank: m.mfi_bank,
+            marginfi_bank_liquidity_vault_authority: m.mfi_bank_liq_vault_auth,
+            marginfi_bank_liquidity_vault: m.mfi_bank_liq_vault,
+            token_program: m.token_program,
         };
-        let tx = self.program
-            .request()
-            .accounts(accounts)
-            .args(args::RedeemUsdcMarginfi).instructions()?.remove(0);
+        // Both instructions take the same `WithdrawUsdcMarginfi` accounts.
+        let request = self.program.request().accounts(accounts);
+        let tx = match amount {
+            Some(amount) => request.args(args::WithdrawUsdcMarginfi { amount }),
+            None => request.args(args::RedeemUsdcMarginfi { min_out: min_out(self.position(m, &user)?.marginfi) }),
+        }.instructions()?.remove(0);
 
         let signature = self.program
             .request()
@@ -136,31 +380,33 @@ impl Rpc {
         Ok(signature.to_string())
     }
 
-    pub fn deposit_to_marginfi(&self, user: Pubkey, amount: u64) -> Result<String> {
-        info!(%user, "deposing to marginfi for");
+    pub fn deposit_to_marginfi(&self, m: &MintMarkets, user: Pubkey, amount: u64) -> Result<String> {
+        info!(%user, symbol = %m.symbol, "deposing to marginfi for");
         if amount == 0 {
             return Err(anyhow!("amount must be > 0"));
         }
-        let (vault_pda, _bump) = Self::vault_pda(&user);
-        let vault_usdc_ata = Self::ata(&vault_pda, &self.usdc_mint);
+        let vault_pda = self.vault_address(&user, &m.mint);
+        let vault_usdc_ata = m.ata(&vault_pda);
         let vault_pda_acc: UserVault = self.program.account(vault_pda)?;
         let marginfi_account = vault_pda_acc.marginfi_account;
         info!(%marginfi_account, %vault_pda, "marginfi account from vault");
 
         let accounts = accounts::DeployUsdcMarginfi{
             keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
             user: user,
-            usdc_mint: self.usdc_mint,
+            usdc_mint: m.mint,
             user_vault_account: vault_pda,
             user_usdc_vault_ata: vault_usdc_ata,
             // marginfi CPI accounts
             marginfi_program: self.mfi_program,
-            marginfi_group: self.mfi_group,
+            marginfi_group: m.mfi_group,
             marginfi_account: vault_pda_acc.marginfi_account,
-            marginfi_bank: self.mfi_bank,
+            marginfi_bank: m.mfi_bank,
             
-            marginfi_bank_liquidity_vault: self.mfi_bank_liq_vault,
-            token_program: spl_token::id(),
+            marginfi_bank_liquidity_vault: m.mfi_bank_liq_vault,
+            token_program: m.token_program,
             system_program: system_program::ID,
             associated_token_program: spl_associated_token_account::id(),
         };
@@ -168,7 +414,7 @@ impl Rpc {
         let tx = self.program.
             request()
             .accounts(accounts)
-            .args(args::DeployUsdcMarginfi{amount: amount})
+            .args(args::DeployUsdcMarginfi{amount: amount, min_out: min_out(amount)})
             .instructions()?
             .remove(0);
 
@@ -181,81 +427,468 @@ impl Rpc {
 
     }
 
-    pub fn withdraw_from_kamino(&self, user: Pubkey) -> Result<String> {
-        info!(%user, "withdrawing from KLend for");
-        let (vault_pda, _bump) = Self::vault_pda(&user);
-        let vault_usdc_ata = Self::ata(&vault_pda, &self.usdc_mint);
-        let vault_k_collateral_ata = Self::ata(&vault_pda, &self.klend_collateral_mint);
+    pub fn withdraw_from_kamino(&self, m: &MintMarkets, user: Pubkey) -> Result<String> {
+        info!(%user, symbol = %m.symbol, "withdrawing from KLend for");
+        let vault_pda = self.vault_address(&user, &m.mint);
+        let vault_usdc_ata = m.ata(&vault_pda);
+        let vault_k_collateral_ata = Self::ata(&vault_pda, &m.klend_collateral_mint);
         let accounts = accounts::RedeemUsdcKaminio {
             keeper: self.bot_pubkey,
-            usdc_mint: self.usdc_mint,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            treasury_usdc_ta: self.treasury_ta(m)?,
+            usdc_mint: m.mint,
             user,
             user_vault_account: vault_pda,
             user_usdc_vault_ata: vault_usdc_ata,
             // Kamino
             kamino_program: self.klend_program,
-            kamino_lending_market: self.klend_market,
-            kamino_lending_market_authority: self.klend_market_auth,
-            kamino_reserve: self.klend_reserve,
-            kamino_reserve_liquidity_supply: self.klend_reserve_liq_supply,
-            kamino_usdc_collateral_mint: self.klend_collateral_mint,
+            kamino_lending_market: m.klend_market,
+            kamino_lending_market_authority: m.klend_market_auth,
+            kamino_reserve: m.klend_reserve,
+            kamino_reserve_liquidity_supply: m.klend_reserve_liq_supply,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
             kamino_usdc_collateral_vault: vault_k_collateral_ata,
             // Built-ins
             associated_token_program: spl_associated_token_account::id(),
             system_program: system_program::ID,
-            token_program: spl_token::id(),
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
             rent: sysvar::rent::ID,
             instruction_sysvar_account: sysvar::instructions::ID,
         };
-        let tx = self.program.request().accounts(accounts).args(args::RedeemUsdcKaminio).instructions()?.remove(0);
+        let expected = self.position(m, &user)?.kamino;
+        let tx = self.program.request().accounts(accounts).args(args::RedeemUsdcKaminio { min_out: min_out(expected) }).instructions()?.remove(0);
         let signature = self.program
         .request()
+        .instruction(self.refresh_reserve_ix(m)?)
         .instruction(tx)
         .signer(self.bot_kp.insecure_clone())
         .send()?;
         Ok(signature.to_string())
     }
 
-    pub fn deposit_to_kamino(&self, user: Pubkey, amount: u64) -> Result<String> {
-        info!(%user, "deposing to Klend for");
+    // Partial redeem: `amount` is in USDC, the program converts it to kUSDC on-chain.
+    pub fn withdraw_amount_from_kamino(&self, m: &MintMarkets, user: Pubkey, amount: u64) -> Result<String> {
+        info!(%user, symbol = %m.symbol, amount, "partially withdrawing from KLend for");
         if amount == 0 {
             return Err(anyhow!("amount must be > 0"));
         }
-        let (vault_pda, _bump) = Self::vault_pda(&user);
-        let vault_usdc_ata = Self::ata(&vault_pda, &self.usdc_mint);
-        let vault_k_collateral_ata = Self::ata(&vault_pda, &self.klend_collateral_mint);
+        let vault_pda = self.vault_address(&user, &m.mint);
+        let vault_usdc_ata = m.ata(&vault_pda);
+        let vault_k_collateral_ata = Self::ata(&vault_pda, &m.klend_collateral_mint);
+        let accounts = accounts::RedeemUsdcKamino {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            treasury_usdc_ta: self.treasury_ta(m)?,
+            usdc_mint: m.mint,
+            user,
+            user_vault_account: vault_pda,
+            user_usdc_vault_ata: vault_usdc_ata,
+            // Kamino
+            kamino_program: self.klend_program,
+            kamino_lending_market: m.klend_market,
+            kamino_lending_market_authority: m.klend_market_auth,
+            kamino_reserve: m.klend_reserve,
+            kamino_reserve_liquidity_supply: m.klend_reserve_liq_supply,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            kamino_usdc_collateral_vault: vault_k_collateral_ata,
+            // Built-ins
+            associated_token_program: spl_associated_token_account::id(),
+            system_program: system_program::ID,
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
+            rent: sysvar::rent::ID,
+            instruction_sysvar_account: sysvar::instructions::ID,
+        };
+        let tx = self.program.request().accounts(accounts).args(args::RedeemUsdcKamino { amount_usdc: amount, min_out: min_out(amount) }).instructions()?.remove(0);
+        let signature = self.program
+        .request()
+        .instruction(self.refresh_reserve_ix(m)?)
+        .instruction(tx)
+        .signer(self.bot_kp.insecure_clone())
+        .send()?;
+        Ok(signature.to_string())
+    }
+
+    pub fn deposit_to_kamino(&self, m: &MintMarkets, user: Pubkey, amount: u64) -> Result<String> {
+        info!(%user, symbol = %m.symbol, "deposing to Klend for");
+        if amount == 0 {
+            return Err(anyhow!("amount must be > 0"));
+        }
+        let vault_pda = self.vault_address(&user, &m.mint);
+        let vault_usdc_ata = m.ata(&vault_pda);
+        let vault_k_collateral_ata = Self::ata(&vault_pda, &m.klend_collateral_mint);
 
       // Build accounts matching your on-chain `TransferUsdcKamino` struct
       let accounts = accounts::DeployUsdcKamino {
         keeper: self.bot_pubkey,
-        usdc_mint: self.usdc_mint,
+        config: Self::config_pda().0,
+        registry: Self::registry_pda().0,
+        treasury_usdc_ta: self.treasury_ta(m)?,
+        usdc_mint: m.mint,
         user,
         user_vault_account: vault_pda,
         user_usdc_vault_ata: vault_usdc_ata,
         // Kamino
         kamino_program: self.klend_program,
-        kamino_lending_market: self.klend_market,
-        kamino_lending_market_authority: self.klend_market_auth,
-        kamino_reserve: self.klend_reserve,
-        kamino_reserve_liquidity_supply: self.klend_reserve_liq_supply,
-        kamino_usdc_collateral_mint: self.klend_collateral_mint,
+        kamino_lending_market: m.klend_market,
+        kamino_lending_market_authority: m.klend_market_auth,
+        kamino_reserve: m.klend_reserve,
+        kamino_reserve_liquidity_supply: m.klend_reserve_liq_supply,
+        kamino_usdc_collateral_mint: m.klend_collateral_mint,
         kamino_usdc_collateral_vault: vault_k_collateral_ata,
         // Built-ins
         associated_token_program: spl_associated_token_account::id(),
         system_program: system_program::ID,
-        token_program: spl_token::id(),
+        token_program: m.token_program,
+        collateral_token_program: spl_token::id(),
         rent: sysvar::rent::ID,
         instruction_sysvar_account: sysvar::instructions::ID,
     };
 
-    let tx = self.program.request().accounts(accounts).args(args::DeployUsdcKamino{amount: amount}).instructions()?.remove(0);
+    let tx = self.program.request().accounts(accounts).args(args::DeployUsdcKamino{amount: amount, min_out: min_out(amount)}).instructions()?.remove(0);
 
     let signature = self.program
     .request()
+    .instruction(self.refresh_reserve_ix(m)?)
     .instruction(tx)
     .signer(self.bot_kp.insecure_clone())
     .send()?;
     Ok(signature.to_string())
 
     }
+
+    // -------- Program config --------
+
+    // Proves liveness on-chain; the user escape hatch opens once heartbeats stop.
+    pub fn heartbeat(&self) -> Result<String> {
+        let accounts = accounts::KeeperHeartbeat {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+        };
+        let tx = self.program.request().accounts(accounts).args(args::KeeperHeartbeat).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+
+    // Current VaultConfig.paused bitmask (see consts::PAUSE_*).
+    pub fn pause_flags(&self) -> Result<u8> {
+        let (config, _) = Self::config_pda();
+        let config: VaultConfig = self.program.account(config)?;
+        Ok(config.paused)
+    }
+
+    // Timelock delay and the config changes proposed by the admin but not yet executed or cancelled.
+    pub fn pending_config_changes(&self) -> Result<(i64, Vec<PendingChange>)> {
+        let (config, _) = Self::config_pda();
+        let config: VaultConfig = self.program.account(config)?;
+        Ok((config.timelock_delay, config.pending_changes))
+    }
+
+    // Remaining room under the deposit caps: (this user's vault, program-wide); None = no cap.
+    pub fn deposit_headroom(&self, m: &MintMarkets, user: &Pubkey) -> Result<(Option<u64>, Option<u64>)> {
+        let (config, _) = Self::config_pda();
+        let config: VaultConfig = self.program.account(config)?;
+        let vault_headroom = if config.max_vault_deposit == 0 {
+            None
+        } else {
+            // A vault that doesn't exist yet has deposited nothing
+            let deposited = self.user_vault(m, user).map(|v| v.deposited_amount).unwrap_or(0);
+            Some(config.max_vault_deposit.saturating_sub(deposited))
+        };
+        let global_headroom = if config.max_total_deposits == 0 {
+            None
+        } else {
+            Some(config.max_total_deposits.saturating_sub(config.total_deposits))
+        };
+        Ok((vault_headroom, global_headroom))
+    }
+
+    // Treasury token account to pass to redeems; omitted while no fees are configured.
+    pub fn treasury_ta(&self, m: &MintMarkets) -> Result<Option<Pubkey>> {
+        let (config, _) = Self::config_pda();
+        let config: VaultConfig = self.program.account(config)?;
+        if config.management_fee_bps == 0 && config.performance_fee_bps == 0 {
+            return Ok(None);
+        }
+        Ok(Some(m.ata(&config.treasury)))
+    }
+
+    // Creates the VaultConfig on first start. Only the bootstrap keeper key may do this;
+    // the admin defaults to the keeper unless VAULT_ADMIN is set.
+    pub fn ensure_config(&self) -> Result<()> {
+        let (config, _) = Self::config_pda();
+        if self.program.account::<VaultConfig>(config).is_ok() {
+            return Ok(());
+        }
+        let admin = match std::env::var("VAULT_ADMIN") {
+            Ok(admin) => admin.parse().map_err(|e| anyhow!("invalid VAULT_ADMIN: {}", e))?,
+            Err(_) => self.bot_pubkey,
+        };
+        info!(%admin, "initializing program config");
+        let accounts = accounts::InitializeConfig {
+            payer: self.bot_pubkey,
+            config,
+            system_program: system_program::ID,
+        };
+        let tx = self.program.request().accounts(accounts).args(args::InitializeConfig { admin }).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        info!(%signature, %config, "program config initialized");
+        Ok(())
+    }
+
+    // -------- Pooled vaults (one per mint) --------
+
+    pub fn pool_state(&self, m: &MintMarkets) -> Result<VaultState> {
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        let state: VaultState = self.program.account(vault_state)?;
+        Ok(state)
+    }
+
+    // Values the pool on-chain by simulating `get_pool_position`, as `position` does for a user vault.
+    pub fn pool_position(&self, m: &MintMarkets) -> Result<Position> {
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        let state = self.pool_state(m)?;
+        let accounts = accounts::GetPoolPosition {
+            usdc_mint: m.mint,
+            vault_state,
+            pool_usdc_ata: m.ata(&vault_state),
+            kamino_reserve: m.klend_reserve,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            pool_collateral_vault: Self::ata(&vault_state, &m.klend_collateral_mint),
+            marginfi_account: state.marginfi_account,
+            marginfi_bank: m.mfi_bank,
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
+        };
+        let tx = self.program.request().accounts(accounts).args(args::GetPoolPosition).signed_transaction()?;
+        let sim = self.program.rpc().simulate_transaction(&tx)?.value;
+        if let Some(err) = sim.err {
+            return Err(anyhow!("get_pool_position simulation failed: {:?}, logs: {:?}", err, sim.logs));
+        }
+        let return_data = sim.return_data.ok_or_else(|| anyhow!("get_pool_position returned no data"))?;
+        let bytes = BASE64.decode(&return_data.data.0)?;
+        Ok(Position::try_from_slice(&bytes)?)
+    }
+
+    pub fn pool_idle_balance(&self, m: &MintMarkets) -> Result<u64> {
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        self.spl_balance(m.ata(&vault_state))
+    }
+
+    // Creates the pool for the mint if it does not exist yet. Keeper pays rent.
+    pub fn ensure_pool(&self, m: &MintMarkets) -> Result<()> {
+        if self.pool_state(m).is_ok() {
+            return Ok(());
+        }
+        info!(symbol = %m.symbol, "initializing pooled vault");
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        let (share_mint, _) = Self::share_mint_pda(&vault_state);
+        let marginfi_account = Keypair::new();
+        let accounts = accounts::InitializePool {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            usdc_mint: m.mint,
+            vault_state,
+            share_mint,
+            pool_usdc_ata: m.ata(&vault_state),
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            pool_collateral_vault: Self::ata(&vault_state, &m.klend_collateral_mint),
+            marginfi_group: m.mfi_group,
+            marginfi_account: marginfi_account.pubkey(),
+            marginfi_program: self.mfi_program,
+            system_program: system_program::ID,
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
+            associated_token_program: spl_associated_token_account::id(),
+            rent: sysvar::rent::ID,
+        };
+        let tx = self.program.request().accounts(accounts).args(args::InitializePool).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .signer(marginfi_account)
+            .send()?;
+        info!(%signature, %vault_state, %share_mint, "pooled vault initialized");
+        Ok(())
+    }
+
+    pub fn deposit_pool_to_kamino(&self, m: &MintMarkets, amount: u64) -> Result<String> {
+        info!(symbol = %m.symbol, amount, "deploying pool to KLend");
+        if amount == 0 {
+            return Err(anyhow!("amount must be > 0"));
+        }
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        let accounts = accounts::DeployPoolKamino {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            usdc_mint: m.mint,
+            vault_state,
+            pool_usdc_ata: m.ata(&vault_state),
+            // Kamino
+            kamino_program: self.klend_program,
+            kamino_lending_market: m.klend_market,
+            kamino_lending_market_authority: m.klend_market_auth,
+            kamino_reserve: m.klend_reserve,
+            kamino_reserve_liquidity_supply: m.klend_reserve_liq_supply,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            pool_collateral_vault: Self::ata(&vault_state, &m.klend_collateral_mint),
+            // Built-ins
+            associated_token_program: spl_associated_token_account::id(),
+            system_program: system_program::ID,
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
+            instruction_sysvar_account: sysvar::instructions::ID,
+        };
+        let tx = self.program.request().accounts(accounts).args(args::DeployPoolKamino { amount, min_out: min_out(amount) }).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(self.refresh_reserve_ix(m)?)
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+
+    pub fn withdraw_pool_from_kamino(&self, m: &MintMarkets) -> Result<String> {
+        info!(symbol = %m.symbol, "redeeming pool from KLend");
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        let accounts = accounts::RedeemPoolKamino {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            usdc_mint: m.mint,
+            vault_state,
+            pool_usdc_ata: m.ata(&vault_state),
+            // Kamino
+            kamino_program: self.klend_program,
+            kamino_lending_market: m.klend_market,
+            kamino_lending_market_authority: m.klend_market_auth,
+            kamino_reserve: m.klend_reserve,
+            kamino_reserve_liquidity_supply: m.klend_reserve_liq_supply,
+            kamino_usdc_collateral_mint: m.klend_collateral_mint,
+            pool_collateral_vault: Self::ata(&vault_state, &m.klend_collateral_mint),
+            // Built-ins
+            associated_token_program: spl_associated_token_account::id(),
+            system_program: system_program::ID,
+            token_program: m.token_program,
+            collateral_token_program: spl_token::id(),
+            instruction_sysvar_account: sysvar::instructions::ID,
+        };
+        let expected = self.pool_position(m)?.kamino;
+        let tx = self.program.request().accounts(accounts).args(args::RedeemPoolKamino { min_out: min_out(expected) }).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(self.refresh_reserve_ix(m)?)
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+
+    pub fn deposit_pool_to_marginfi(&self, m: &MintMarkets, amount: u64) -> Result<String> {
+        info!(symbol = %m.symbol, amount, "deploying pool to marginfi");
+        if amount == 0 {
+            return Err(anyhow!("amount must be > 0"));
+        }
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        let state = self.pool_state(m)?;
+        let accounts = accounts::DeployPoolMarginfi {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            usdc_mint: m.mint,
+            vault_state,
+            pool_usdc_ata: m.ata(&vault_state),
+            // marginfi CPI accounts
+            marginfi_group: m.mfi_group,
+            marginfi_account: state.marginfi_account,
+            marginfi_bank: m.mfi_bank,
+            marginfi_bank_liquidity_vault_authority: m.mfi_bank_liq_vault_auth,
+            marginfi_bank_liquidity_vault: m.mfi_bank_liq_vault,
+            marginfi_program: self.mfi_program,
+            token_program: m.token_program,
+        };
+        let tx = self.program.request().accounts(accounts).args(args::DeployPoolMarginfi { amount, min_out: min_out(amount) }).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+
+    pub fn withdraw_pool_from_marginfi(&self, m: &MintMarkets) -> Result<String> {
+        info!(symbol = %m.symbol, "redeeming pool from marginfi");
+        let (vault_state, _) = Self::vault_state_pda(&m.mint);
+        let state = self.pool_state(m)?;
+        let accounts = accounts::RedeemPoolMarginfi {
+            keeper: self.bot_pubkey,
+            config: Self::config_pda().0,
+            registry: Self::registry_pda().0,
+            usdc_mint: m.mint,
+            vault_state,
+            pool_usdc_ata: m.ata(&vault_state),
+            // marginfi CPI accounts
+            marginfi_group: m.mfi_group,
+            marginfi_account: state.marginfi_account,
+            marginfi_bank: m.mfi_bank,
+            marginfi_bank_liquidity_vault_authority: m.mfi_bank_liq_vault_auth,
+            marginfi_bank_liquidity_vault: m.mfi_bank_liq_vault,
+            marginfi_program: self.mfi_program,
+            token_program: m.token_program,
+        };
+        let expected = self.pool_position(m)?.marginfi;
+        let tx = self.program.request().accounts(accounts).args(args::RedeemPoolMarginfi { min_out: min_out(expected) }).instructions()?.remove(0);
+        let signature = self.program
+            .request()
+            .instruction(tx)
+            .signer(self.bot_kp.insecure_clone())
+            .send()?;
+        Ok(signature.to_string())
+    }
+}
+
+// Least acceptable outcome for an operation expected to yield `expected`. Expected values come from
+// a simulated `get_position`, which may lag the refreshed on-chain state but never runs ahead of it.
+fn min_out(expected: u64) -> u64 {
+    (expected as u128 * (10_000 - MAX_SLIPPAGE_BPS) as u128 / 10_000) as u64
+}
+
+// UserVault as created before versioning (layout version 0), the fields the migration needs.
+// Later fields were appended, so the baseline fields are a prefix of the current layout.
+#[derive(AnchorDeserialize, Debug)]
+pub struct BaselineUserVault {
+    pub bump: u8,
+    pub owner: Pubkey,
+    pub marginfi_account: Pubkey,
+    pub deposited_amount: u64,
+}
+
+// Decodes a UserVault account still on the baseline layout; None for vaults already on the current one.
+fn baseline_vault(data: &[u8]) -> Option<BaselineUserVault> {
+    if UserVault::try_deserialize(&mut &data[..]).is_ok() {
+        return None;
+    }
+    let body = data.strip_prefix(&UserVault::DISCRIMINATOR[..])?;
+    BaselineUserVault::deserialize(&mut &body[..]).ok()
+}
+
+fn protocol_of(strategy: Strategy) -> yield_vault::types::Protocol {
+    match strategy {
+        Strategy::Kamino   => yield_vault::types::Protocol::Kamino,
+        Strategy::Marginfi => yield_vault::types::Protocol::Marginfi,
+    }
 }
\ No newline at end of file
@@ -6,10 +6,17 @@ use anchor_lang::prelude::*;
 use reqwest::Client;
 use crate::marginfi_apy;
 
-use crate::config::{AppState, Strategy};
+use crate::config::{AppState, Strategy, Weights, BPS};
+use crate::consts::{PAUSE_DEPLOYS, PAUSE_REBALANCES};
+use crate::markets::MintMarkets;
+use crate::yield_vault::types::Protocol;
 
 // Tick every hour
 const ONE_HOUR: Duration = Duration::from_secs(3600);
+// Neither protocol's share goes below this, so one protocol failing can't take everything
+const MIN_WEIGHT_BPS: u16 = 2_000;
+// Weights move in steps of this size; smaller APY moves don't trigger a reallocation
+const REWEIGHT_STEP_BPS: u16 = 500;
 
 pub async fn bootstrap_once(app: AppState) {
     match tick_once(&app).await {
@@ -45,22 +52,22 @@ struct KaminoMetrics {
     supplyInterestAPY: f64, // decimal (e.g., 0.035 for 3.5%)
 }
 
-// Build the Kamino URL based on your consts and a small lookback window
-fn kamino_url() -> Result<String> {
+// Build the Kamino URL for the mint's reserve and a small lookback window
+fn kamino_url(m: &MintMarkets) -> Result<String> {
     use chrono::{Utc, Duration as ChDur, SecondsFormat};
     let end = Utc::now();
     let start = end - ChDur::hours(12);
     Ok(format!(
         "https://api.kamino.finance/kamino-market/{}/reserves/{}/metrics/history?start={}&end={}",
-        crate::consts::KLEND_MAIN_LENDING_MARKET,
-        crate::consts::KLEND_USDC_RESEVE,
+        m.klend_market,
+        m.klend_reserve,
         start.to_rfc3339_opts(SecondsFormat::Secs, true),
         end.to_rfc3339_opts(SecondsFormat::Secs, true),
     ))
 }
 
-async fn kamino_supply_apy(client: &Client) -> Result<f64> {
-    let url = kamino_url()?;
+async fn kamino_supply_apy(client: &Client, m: &MintMarkets) -> Result<f64> {
+    let url = kamino_url(m)?;
     let resp = client.get(&url)
         .timeout(Duration::from_secs(10))
         .send().await.context("kamino http")?
@@ -71,82 +78,187 @@ async fn kamino_supply_apy(client: &Client) -> Result<f64> {
 }
 
 // For now: fixed 1% APY on Marginfi;
-async fn marginfi_supply_apy(client: &Client) -> Result<f64> {
-    let bank_pk = crate::consts::MARGINFI_BANK;
-    marginfi_apy::fetch_marginfi_supply_apy(client, bank_pk)
+async fn marginfi_supply_apy(client: &Client, m: &MintMarkets) -> Result<f64> {
+    let bank_pk = m.mfi_bank.to_string();
+    marginfi_apy::fetch_marginfi_supply_apy(client, &bank_pk)
         .await
         .context("marginfi apy fetch")
 }
 
 
 async fn tick_once(app: &AppState) -> Result<()> {
-    // log start of attempt
-    info!("tracker: fetching APYs…");
+    // Heartbeat first, so a failing APY source doesn't open the users' escape hatch
+    if let Err(e) = tokio::task::block_in_place(|| app.rpc.heartbeat()) {
+        warn!(error=?e, "tracker: heartbeat failed");
+    }
 
+    // The guardian can pause deploys and rebalances on-chain; don't send transactions that would fail
+    let paused = tokio::task::block_in_place(|| app.rpc.pause_flags()).context("pause flags")?;
+    let deploys_paused = paused & PAUSE_DEPLOYS != 0;
+    let rebalances_paused = paused & PAUSE_REBALANCES != 0;
+    if paused != 0 {
+        warn!(paused, deploys_paused, rebalances_paused, "tracker: program is paused");
+    }
+
+    // Every mint runs its own strategy; one failing mint doesn't hold up the others
     let client = Client::new();
-    let kam_apy = kamino_supply_apy(&client).await.context("kamino apy")?;
+    for m in &app.rpc.markets {
+        if let Err(e) = tick_mint(app, &client, m, deploys_paused, rebalances_paused).await {
+            error!(symbol = %m.symbol, error=?e, "tracker: mint tick failed");
+        }
+    }
+    Ok(())
+}
+
+async fn tick_mint(app: &AppState, client: &Client, m: &MintMarkets, deploys_paused: bool, rebalances_paused: bool) -> Result<()> {
+    // log start of attempt
+    info!(symbol = %m.symbol, "tracker: fetching APYs…");
 
-    let mfi_apy = marginfi_supply_apy(&client).await.context("marginfi apy")?;
+    let kam_apy = kamino_supply_apy(client, m).await.context("kamino apy")?;
 
+    let mfi_apy = marginfi_supply_apy(client, m).await.context("marginfi apy")?;
 
-    info!(kam_apy = ?kam_apy, mfi_apy = ?mfi_apy, "tracker: APYs fetched");
 
-    // Decide desired strategy
-    // TODO: add logic to decide based on APYs, risk tolerance, transactions fees etc.
-    let desired = if kam_apy > mfi_apy { Strategy::Kamino } else { Strategy::Marginfi };
+    info!(symbol = %m.symbol, kam_apy = ?kam_apy, mfi_apy = ?mfi_apy, "tracker: APYs fetched");
 
-    // Compare to current and flip if needed
-    let mut lock = app.strategy.write().await;
-    let current = *lock;
-    info!(?current, ?desired, "tracker: decision");
+    // Decide target weights
+    // TODO: factor in risk tolerance, transaction fees etc.
+    let desired = weights_for(kam_apy, mfi_apy);
 
-    if desired != current {
-        info!(?current, ?desired, "tracker: flipping strategy and rebalancing");
+    // Compare to current and reallocate if needed
+    let mut all_weights = app.weights.write().await;
+    let current = *all_weights.get(&m.mint).unwrap_or(&Weights::all(Strategy::Marginfi));
+    info!(symbol = %m.symbol, %current, %desired, "tracker: decision");
 
-        // (Optional): unwind & redeploy for all tracked users
-        let users: Vec<Pubkey> = app.lenders.read().await.iter().cloned().collect();
+    if desired != current && (rebalances_paused || deploys_paused) {
+        // Keep the current weights so the move is retried once the program is resumed
+        warn!(symbol = %m.symbol, %current, %desired, "tracker: rebalances or deploys paused, not reweighting");
+    } else if desired != current {
+        info!(symbol = %m.symbol, %current, %desired, "tracker: reweighting and reallocating");
 
-        // Unwind from current
+        let users: Vec<Pubkey> = app.lenders.read().await.iter()
+            .filter(|(_, mint)| *mint == m.mint)
+            .map(|(user, _)| *user)
+            .collect();
+
+        // Each vault moves only the delta to the new weights, in a single transaction
         for u in &users {
-            let res = if current == Strategy::Kamino {
-                tokio::task::block_in_place(|| app.rpc.withdraw_from_kamino(*u))
-            } else {
-                tokio::task::block_in_place(|| app.rpc.withdraw_from_marginfi(*u))
-            };
-            if let Err(e) = res {
-                warn!(user=%u, error=?e, "tracker: unwind failed");
-            } else {
-                info!(user=%u, "tracker: unwind ok");
+            let res = tokio::task::block_in_place(|| allocate_user(app, m, u, desired));
+            match res {
+                Ok(Some(sig)) => info!(user=%u, symbol = %m.symbol, %sig, %desired, "tracker: allocate ok"),
+                Ok(None)      => info!(user=%u, symbol = %m.symbol, "tracker: nothing to allocate"),
+                Err(e)        => warn!(user=%u, symbol = %m.symbol, error=?e, "tracker: allocate failed"),
             }
         }
 
-        // Redeploy into desired
-        for u in &users {
-            // Determine amount to redeploy = vault ATA balance
-            let (vault_pda, _) = crate::rpc::Rpc::vault_pda(u);
-            let ata = crate::rpc::Rpc::ata(&vault_pda, &app.rpc.usdc_mint);
-            let amount = app.rpc.spl_balance(ata).unwrap_or(0);
-            if amount == 0 {
-                warn!(user=%u, "tracker: no balance to redeploy");
-                continue;
+        // Pooled vault: one unwind for every share holder; the sweep below redeploys it at the new weights
+        if let Err(e) = tokio::task::block_in_place(|| unwind_pool(app, m)) {
+            warn!(symbol = %m.symbol, error=?e, "tracker: pool unwind failed");
+        }
+
+        all_weights.insert(m.mint, desired);
+        info!(symbol = %m.symbol, %desired, "tracker: weights updated");
+    } else {
+        info!(symbol = %m.symbol, "tracker: weights unchanged");
+    }
+    let weights = *all_weights.get(&m.mint).unwrap_or(&desired);
+    drop(all_weights);
+
+    // Deploy whatever sits idle in the pool (new deposits, unwound funds) at the current weights
+    if deploys_paused {
+        info!(symbol = %m.symbol, "tracker: deploys paused, skipping pool sweep");
+    } else if let Err(e) = tokio::task::block_in_place(|| sweep_pool(app, m, weights)) {
+        warn!(symbol = %m.symbol, error=?e, "tracker: pool sweep failed");
+    }
+
+    Ok(())
+}
+
+// Kamino's share in proportion to its APY, clamped to [MIN_WEIGHT_BPS, BPS - MIN_WEIGHT_BPS]
+// and rounded to REWEIGHT_STEP_BPS. Even split when neither protocol pays anything.
+fn weights_for(kam_apy: f64, mfi_apy: f64) -> Weights {
+    let (kam_apy, mfi_apy) = (kam_apy.max(0.0), mfi_apy.max(0.0));
+    let share = if kam_apy + mfi_apy > 0.0 { kam_apy / (kam_apy + mfi_apy) } else { 0.5 };
+    let step = REWEIGHT_STEP_BPS as f64;
+    let kamino_bps = ((share * BPS as f64 / step).round() * step) as u16;
+    Weights { kamino_bps: kamino_bps.clamp(MIN_WEIGHT_BPS, BPS - MIN_WEIGHT_BPS) }
+}
+
+// Moves a user vault to `weights`; skipped when the vault holds nothing at all.
+pub fn allocate_user(app: &AppState, m: &MintMarkets, user: &Pubkey, weights: Weights) -> Result<Option<String>> {
+    let vault = app.rpc.user_vault(m, user)?;
+    if vault.active_protocol == Protocol::None {
+        let vault_pda = app.rpc.vault_address(user, &m.mint);
+        if app.rpc.spl_balance(m.ata(&vault_pda))? == 0 {
+            return Ok(None);
+        }
+    }
+    Ok(Some(app.rpc.allocate(m, *user, weights)?))
+}
+
+// Redeems a user vault from the protocols recorded in its `active_protocol`:
+// everything when `amount` is None, otherwise about `amount` USDC
+// (taken from a split vault in proportion to its weights).
+pub fn unwind_user(app: &AppState, m: &MintMarkets, user: &Pubkey, amount: Option<u64>) -> Result<Option<String>> {
+    let vault = app.rpc.user_vault(m, user)?;
+    let sig = match (vault.active_protocol, amount) {
+        (Protocol::Kamino, None)         => app.rpc.withdraw_from_kamino(m, *user)?,
+        (Protocol::Kamino, Some(amount)) => app.rpc.withdraw_amount_from_kamino(m, *user, amount)?,
+        (Protocol::Marginfi, amount)     => app.rpc.withdraw_from_marginfi(m, *user, amount)?,
+        (Protocol::Split, None)          => {
+            let kamino = app.rpc.withdraw_from_kamino(m, *user)?;
+            let marginfi = app.rpc.withdraw_from_marginfi(m, *user, None)?;
+            format!("{},{}", kamino, marginfi)
+        }
+        (Protocol::Split, Some(amount))  => {
+            let (kamino, marginfi) = Weights { kamino_bps: vault.kamino_weight_bps }.split(amount);
+            let mut sigs = Vec::new();
+            if kamino > 0 {
+                sigs.push(app.rpc.withdraw_amount_from_kamino(m, *user, kamino)?);
             }
-            let res = if desired == Strategy::Kamino {
-                tokio::task::block_in_place(|| app.rpc.deposit_to_kamino(*u, amount))
-            } else {
-                tokio::task::block_in_place(|| app.rpc.deposit_to_marginfi(*u, amount))
-            };
-            if let Err(e) = res {
-                warn!(user=%u, error=?e, "tracker: redeploy failed");
-            } else {
-                info!(user=%u, amount, ?desired, "tracker: redeploy ok");
+            if marginfi > 0 {
+                sigs.push(app.rpc.withdraw_from_marginfi(m, *user, Some(marginfi))?);
             }
+            sigs.join(",")
         }
+        (Protocol::None, _)              => return Ok(None),
+    };
+    Ok(Some(sig))
+}
 
-        *lock = desired;
-        info!(?desired, "tracker: strategy updated");
-    } else {
-        info!("tracker: strategy unchanged");
+// Redeems the pool from every protocol it is deployed to.
+fn unwind_pool(app: &AppState, m: &MintMarkets) -> Result<()> {
+    let state = match app.rpc.pool_state(m) {
+        Ok(state) => state,
+        Err(_) => return Ok(()), // no pool yet
+    };
+    if state.kamino_deployed > 0 {
+        let sig = app.rpc.withdraw_pool_from_kamino(m)?;
+        info!(symbol = %m.symbol, %sig, "tracker: pool unwind from kamino ok");
+    }
+    if state.marginfi_deployed > 0 {
+        let sig = app.rpc.withdraw_pool_from_marginfi(m)?;
+        info!(symbol = %m.symbol, %sig, "tracker: pool unwind from marginfi ok");
     }
+    Ok(())
+}
 
+fn sweep_pool(app: &AppState, m: &MintMarkets, weights: Weights) -> Result<()> {
+    if app.rpc.pool_state(m).is_err() {
+        return Ok(());
+    }
+    let idle = app.rpc.pool_idle_balance(m)?;
+    if idle == 0 {
+        return Ok(());
+    }
+    let (kamino, marginfi) = weights.split(idle);
+    if kamino > 0 {
+        let sig = app.rpc.deposit_pool_to_kamino(m, kamino)?;
+        info!(symbol = %m.symbol, %sig, kamino, "tracker: pool sweep to kamino ok");
+    }
+    if marginfi > 0 {
+        let sig = app.rpc.deposit_pool_to_marginfi(m, marginfi)?;
+        info!(symbol = %m.symbol, %sig, marginfi, "tracker: pool sweep to marginfi ok");
+    }
     Ok(())
 }
\ No newline at end of file
@@ -1,4 +1,7 @@
 // DO NOT add zero_copy/packed for foreign program accounts.
 // Many aren’t POD and it will fail to compile.
 
+// The generated IDL helpers still call the deprecated `AccountInfo::realloc`
+#![allow(deprecated)]
+
 anchor_gen::generate_cpi_crate!("src/idl.json");
\ No newline at end of file
@@ -0,0 +1,98 @@
+// Typed events for indexers; every vault state change emits one of these next to its msg! log.
+// `vault` is the user vault PDA, or the pool VaultState for pooled deploys/redeems.
+use anchor_lang::prelude::*;
+
+use crate::Protocol;
+
+#[event]
+pub struct VaultInitialized {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub marginfi_account: Pubkey,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VaultClosed {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub marginfi_account: Pubkey,
+    pub slot: u64,
+}
+
+#[event]
+pub struct Deposited {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub principal: u64,     // vault principal after the deposit
+    pub protocol: Protocol, // where the vault's funds are deployed
+    pub slot: u64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub principal: u64,     // vault principal after the withdrawal
+    pub protocol: Protocol, // where the vault's funds are deployed
+    pub slot: u64,
+}
+
+#[event]
+pub struct DeployedToKamino {
+    pub vault: Pubkey,
+    pub amount: u64,              // USDC deposited
+    pub collateral_received: u64, // kUSDC minted to the vault
+    pub slot: u64,
+}
+
+#[event]
+pub struct RedeemedFromKamino {
+    pub vault: Pubkey,
+    pub collateral_amount: u64, // kUSDC burned
+    pub amount_received: u64,   // USDC returned, before fees
+    pub fee: u64,               // fees paid to the treasury
+    pub slot: u64,
+}
+
+#[event]
+pub struct DeployedToMarginfi {
+    pub vault: Pubkey,
+    pub amount: u64, // USDC deposited
+    pub slot: u64,
+}
+
+#[event]
+pub struct RedeemedFromMarginfi {
+    pub vault: Pubkey,
+    pub amount_received: u64, // USDC returned, before fees
+    pub fee: u64,             // fees paid to the treasury
+    pub slot: u64,
+}
+
+#[event]
+pub struct Rebalanced {
+    pub vault: Pubkey,
+    pub from: Protocol,
+    pub to: Protocol,
+    pub amount_redeemed: u64,     // USDC received from `from`
+    pub fee: u64,                 // fees paid to the treasury
+    pub amount_deployed: u64,     // USDC deposited into `to`
+    pub collateral_received: u64, // kUSDC minted when `to` is Kamino, else 0
+    pub slot: u64,
+}
+
+#[event]
+pub struct Allocated {
+    pub vault: Pubkey,
+    pub kamino_weight_bps: u16,
+    pub total: u64,           // idle + Kamino + Marginfi value before the move
+    pub kamino_before: u64,   // Kamino position value before the move
+    pub marginfi_before: u64, // Marginfi position value before the move
+    pub kamino_target: u64,
+    pub marginfi_target: u64,
+    pub slot: u64,
+}
@@ -1,20 +1,37 @@
+// Anchor's generated IDL account handlers (from `#[program]`) still call the deprecated `AccountInfo::realloc`
+#![allow(deprecated)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{
+use anchor_spl::token::Token;
+use anchor_spl::token_interface::{
     Mint, 
-    Token, 
     TokenAccount,
-    Transfer, 
-    transfer
+    TokenInterface,
+    TransferChecked, 
+    transfer_checked,
+    MintTo,
+    mint_to,
+    Burn,
+    burn,
+    CloseAccount,
+    close_account,
 };
 use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::system_program;
 use anchor_spl::associated_token::AssociatedToken;
-use kamino_lend::cpi as kamino_cpi;
 use kamino_lend::program::KaminoLending;
 
 use marginfi_cpi_local::program::Marginfi;
 use marginfi_cpi_local::cpi::accounts as mfi_accounts; 
 use marginfi_cpi_local::cpi as mfi_cpi;              
 
+pub mod protocols;
+use protocols::{check_kamino_reserve, check_marginfi_bank, marginfi_asset_value, KaminoCpi, KaminoExchangeRate, MarginfiCpi};
+pub mod events;
+use events::*;
+
+// Bootstrap authority: only allowed to create the VaultConfig. Keeper authorization
+// afterwards comes from the config's keeper set.
 pub const KEEPER_PUBKEY: &str = "bot7F9sfkm5ztmMGL11St2PD9necoEY6fC84L1WKMDg";
 pub fn keeper_pubkey() -> Pubkey {
     KEEPER_PUBKEY.parse().unwrap()
@@ -43,6 +60,13 @@ pub mod yield_vault {
         user_vault.owner = ctx.accounts.user.key();
         user_vault.bump = ctx.bumps.user_vault_account;
         user_vault.marginfi_account = ctx.accounts.marginfi_account.key();
+        user_vault.active_protocol = Protocol::None;
+        user_vault.fee_checkpoint = 0;
+        user_vault.realized_yield = 0;
+        user_vault.withdrawn_amount = 0;
+        user_vault.mint = ctx.accounts.usdc_mint.key();
+        user_vault.legacy_seeds = false;
+        user_vault.version = UserVault::VERSION;
 
         // Marginfi CPI: Initialize the marginfi account
         let cpi_accounts = mfi_accounts::MarginfiAccountInitialize {
@@ -59,14 +83,25 @@ pub mod yield_vault {
         
         msg!("Marginfi Account initialized: '{}'", user_vault.marginfi_account.to_string());
         msg!("Vault initialized for owner: {}", user_vault.owner.to_string());
+        emit!(VaultInitialized {
+            vault: user_vault.key(),
+            owner: user_vault.owner,
+            mint: user_vault.mint,
+            marginfi_account: user_vault.marginfi_account,
+            slot: Clock::get()?.slot,
+        });
         Ok(())
     }
 
+    // Pays out idle USDC of a vault with nothing deployed. A vault with open positions withdraws
+    // through `withdraw_from_protocol`, which values them and settles fees before paying out.
     pub fn withdraw(ctx: Context<TransferAssets>, amount: u64) -> Result<()> {
         require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        require!(ctx.accounts.user_vault_account.active_protocol == Protocol::None, YieldVaultErrors::FundsStillDeployed);
         msg!("Withdrawing {} from USDC vault", amount);
-        let vault_withdraw_accounts = Transfer {
+        let vault_withdraw_accounts = TransferChecked {
             from: ctx.accounts.user_usdc_vault_ata.to_account_info(),
+            mint: ctx.accounts.usdc_mint.to_account_info(),
             to: ctx.accounts.user_usdc_ta.to_account_info(),
             authority: ctx.accounts.user_vault_account.to_account_info(),
         };
@@ -77,166 +112,1389 @@ pub mod yield_vault {
             ctx.accounts.token_program.to_account_info(), 
             vault_withdraw_accounts,
             signer);
-        transfer(cpi_context, amount)?;
-
-        // ctx.accounts.user_vault_account.deposited_amount = ctx.accounts.user_vault_account.deposited_amount.checked_sub(amount).ok_or(ProgramError::InvalidAccountData)?;
+        transfer_checked(cpi_context, amount, ctx.accounts.usdc_mint.decimals)?;
+        ctx.accounts.config.record_withdrawal(amount);
+        ctx.accounts.user_vault_account.record_withdrawal(amount)?;
         msg!("Withdrawn {} USDC from vault {} of owner {}", amount, ctx.accounts.user_vault_account.key(), ctx.accounts.user.key().to_string());
+        emit!(Withdrawn {
+            vault: ctx.accounts.user_vault_account.key(),
+            owner: ctx.accounts.user.key(),
+            amount,
+            principal: ctx.accounts.user_vault_account.deposited_amount,
+            protocol: ctx.accounts.user_vault_account.active_protocol,
+            slot: Clock::get()?.slot,
+        });
         Ok(())
     }
 
     pub fn deposit(ctx: Context<TransferAssets>, amount: u64) -> Result<()> {
         require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        ctx.accounts.config.require_not_paused(PAUSE_DEPOSITS)?;
         msg!("Depositing {} to USDC vault", amount);
-        let vault_deposit_accounts = Transfer {
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
+        let vault_deposit_accounts = TransferChecked {
             from: ctx.accounts.user_usdc_ta.to_account_info(),
+            mint: ctx.accounts.usdc_mint.to_account_info(),
             to: ctx.accounts.user_usdc_vault_ata.to_account_info(),
             authority: ctx.accounts.user.to_account_info(),
 
         };
         let cpi_context = CpiContext::new(ctx.accounts.token_program.to_account_info(), vault_deposit_accounts);
-        transfer(cpi_context, amount)?;
+        transfer_checked(cpi_context, amount, ctx.accounts.usdc_mint.decimals)?;
 
-        ctx.accounts.user_vault_account.deposited_amount = ctx.accounts.user_vault_account.deposited_amount.checked_add(amount).ok_or(ProgramError::InvalidAccountData)?;
-        msg!("Deposited {} USDC to vault {} of owner {}", amount, ctx.accounts.user_vault_account.key(), ctx.accounts.user.key().to_string());
+        // Token-2022 transfer fees are withheld in the destination; only credit what arrived.
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.checked_sub(before).ok_or(YieldVaultErrors::MathOverflow)?;
+        require!(received > 0, YieldVaultErrors::InvalidAmount);
+        let vault_total = ctx.accounts.user_vault_account.deposited_amount.checked_add(received).ok_or(YieldVaultErrors::MathOverflow)?;
+        ctx.accounts.config.record_deposit(Some(vault_total), received)?;
+
+        ctx.accounts.user_vault_account.deposited_amount = vault_total;
+        msg!("Deposited {} USDC to vault {} of owner {}", received, ctx.accounts.user_vault_account.key(), ctx.accounts.user.key().to_string());
+        emit!(Deposited {
+            vault: ctx.accounts.user_vault_account.key(),
+            owner: ctx.accounts.user.key(),
+            amount: received,
+            principal: vault_total,
+            protocol: ctx.accounts.user_vault_account.active_protocol,
+            slot: Clock::get()?.slot,
+        });
         Ok(())
     }
 
-    pub fn deploy_usdc_marginfi(ctx: Context<DepositUsdcMarginfi>, amount: u64) -> Result<()> {
+    // User-signed deposit that deploys what arrived straight into the protocol recorded on the vault
+    // (split by `kamino_weight_bps` for a Split vault), so no keeper round trip is needed.
+    // A vault with nothing deployed keeps the deposit idle for the keeper to allocate.
+    // Deploying to Kamino needs the reserve refreshed earlier in the same transaction.
+    // Fails with SlippageExceeded unless the positions it opened are worth at least `min_out`.
+    pub fn deposit_and_deploy(ctx: Context<TransferProtocolAssets>, amount: u64, min_out: u64) -> Result<()> {
         require!(amount > 0, YieldVaultErrors::InvalidAmount);
-
-        // Marginfi CPI: Deposit USDC into the marginfi account
-        let user_vault = &mut ctx.accounts.user_vault_account; 
-        let cpi_accounts = mfi_accounts::LendingAccountDeposit {
-            group:                  ctx.accounts.marginfi_group.to_account_info(),
-            marginfi_account:       ctx.accounts.marginfi_account.to_account_info(),
-            authority:              user_vault.to_account_info(),
-            bank:                   ctx.accounts.marginfi_bank.to_account_info(),
-            signer_token_account:   ctx.accounts.user_usdc_vault_ata.to_account_info(),
-            liquidity_vault:        ctx.accounts.marginfi_bank_liquidity_vault.to_account_info(),
-            token_program:          ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.config.require_not_paused(PAUSE_DEPOSITS)?;
+        ctx.accounts.config.require_not_paused(PAUSE_DEPLOYS)?;
+        let received = ctx.accounts.receive_deposit(amount)?;
+
+        let active_protocol = ctx.accounts.user_vault_account.active_protocol;
+        let kamino_amount = match active_protocol {
+            Protocol::Kamino => received,
+            Protocol::Split => mul_div(received, ctx.accounts.user_vault_account.kamino_weight_bps as u64, BPS_DENOMINATOR)?,
+            Protocol::Marginfi | Protocol::None => 0,
         };
-    
-        let signer: &[&[&[u8]]] = &[&user_vault.seeds()]; 
-        let cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.marginfi_program.to_account_info(), cpi_accounts, signer
+        let marginfi_amount = match active_protocol {
+            Protocol::None => 0,
+            _ => received - kamino_amount,
+        };
+
+        let usdc_mint = ctx.accounts.usdc_mint.key();
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        // USDC value of the positions this deposit opened, at the post-deposit rates
+        let mut landed = 0u64;
+        if kamino_amount > 0 {
+            ctx.accounts.registry.require_kamino(&usdc_mint, &ctx.accounts.kamino_reserve.key())?;
+            let collateral_before = ctx.accounts.kamino_usdc_collateral_vault.amount;
+            ctx.accounts.kamino_cpi().deposit(signer, kamino_amount)?;
+            ctx.accounts.kamino_usdc_collateral_vault.reload()?;
+            let collateral_received = ctx.accounts.kamino_usdc_collateral_vault.amount.saturating_sub(collateral_before);
+            landed = KaminoExchangeRate::load(&ctx.accounts.kamino_reserve.to_account_info())?.liquidity_for_collateral(collateral_received)?;
+            emit!(DeployedToKamino {
+                vault: ctx.accounts.user_vault_account.key(),
+                amount: kamino_amount,
+                collateral_received,
+                slot: Clock::get()?.slot,
+            });
+        }
+        if marginfi_amount > 0 {
+            ctx.accounts.registry.require_marginfi(&usdc_mint, &ctx.accounts.marginfi_bank.key())?;
+            let value_before = ctx.accounts.marginfi_value()?;
+            ctx.accounts.marginfi_cpi().deposit(signer, marginfi_amount)?;
+            let deployed = ctx.accounts.marginfi_value()?.saturating_sub(value_before);
+            landed = landed.checked_add(deployed).ok_or(YieldVaultErrors::MathOverflow)?;
+            emit!(DeployedToMarginfi {
+                vault: ctx.accounts.user_vault_account.key(),
+                amount: marginfi_amount,
+                slot: Clock::get()?.slot,
+            });
+        }
+        require!(landed >= min_out, YieldVaultErrors::SlippageExceeded);
+        msg!(
+            "Deposited {} USDC to vault {} of owner {}: {} to Kamino, {} to Marginfi",
+            received, ctx.accounts.user_vault_account.key(), ctx.accounts.user.key().to_string(), kamino_amount, marginfi_amount
         );
-    
-        mfi_cpi::lending_account_deposit(cpi_ctx, amount, Some(true))?; 
-        Ok(())
-    }
-
-    pub fn redeem_usdc_marginfi(ctx: Context<WithdrawUsdcMarginfi>) -> Result<()> {
-        let user_vault = &mut ctx.accounts.user_vault_account;  
-        // Build CPI accounts
-        let cpi_accounts = mfi_accounts::LendingAccountWithdraw {
-            group:                       ctx.accounts.marginfi_group.to_account_info(),
-            marginfi_account:            ctx.accounts.marginfi_account.to_account_info(),
-            authority:                   user_vault.to_account_info(),
-            bank:                        ctx.accounts.marginfi_bank.to_account_info(),
-            destination_token_account:   ctx.accounts.user_usdc_vault_ata.to_account_info(),
-            bank_liquidity_vault_authority: ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info(),
-            liquidity_vault:                ctx.accounts.marginfi_bank_liquidity_vault.to_account_info(),
-            token_program:                  ctx.accounts.token_program.to_account_info(),
+        Ok(())
+    }
+
+    // User-signed withdrawal that redeems whatever the vault ATA is short of `amount` from the vault's
+    // protocols (in proportion to `kamino_weight_bps` for a Split vault) and pays `user_usdc_ta` in the
+    // same instruction, so nothing depends on the keeper or on reading the ATA balance in between.
+    // Fees are settled first against the vault's whole value (idle plus positions), so yield is assessed
+    // before any of it is paid out; the payout is capped at what the vault is worth after them.
+    // A vault holding Kamino collateral needs the reserve refreshed earlier in the same transaction.
+    pub fn withdraw_from_protocol(ctx: Context<TransferProtocolAssets>, amount: u64) -> Result<()> {
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        // The value fees are settled on must come from the registry's reserve and bank, whether or not anything is redeemed
+        let usdc_mint = ctx.accounts.usdc_mint.key();
+        let reserve = ctx.accounts.kamino_reserve.to_account_info();
+        ctx.accounts.registry.require_kamino(&usdc_mint, &reserve.key())?;
+        check_kamino_reserve(&reserve, &usdc_mint, &ctx.accounts.kamino_usdc_collateral_mint.key())?;
+        ctx.accounts.registry.require_marginfi(&usdc_mint, &ctx.accounts.marginfi_bank.key())?;
+        check_marginfi_bank(&ctx.accounts.marginfi_bank.to_account_info(), &usdc_mint)?;
+
+        let active_protocol = ctx.accounts.user_vault_account.active_protocol;
+        let idle = ctx.accounts.user_usdc_vault_ata.amount;
+        let collateral_held = ctx.accounts.kamino_usdc_collateral_vault.amount;
+        let kamino = if active_protocol.includes(Protocol::Kamino) && collateral_held > 0 {
+            ctx.accounts.kamino_cpi().require_refreshed()?;
+            KaminoExchangeRate::load(&reserve)?.liquidity_for_collateral(collateral_held)?
+        } else {
+            0
         };
-    
-        // PDA seeds for the vault authority
-        let signer: &[&[&[u8]]] = &[&user_vault.seeds()]; 
-        let cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.marginfi_program.to_account_info(), cpi_accounts, signer
+        let marginfi = if active_protocol.includes(Protocol::Marginfi) {
+            marginfi_asset_value(&ctx.accounts.marginfi_account.to_account_info(), &ctx.accounts.marginfi_bank.to_account_info())?
+        } else {
+            0
+        };
+        let value = idle
+            .checked_add(kamino)
+            .and_then(|v| v.checked_add(marginfi))
+            .ok_or(YieldVaultErrors::MathOverflow)?;
+        let now = Clock::get()?.unix_timestamp;
+        let fee = ctx.accounts.user_vault_account.settle_fees(&ctx.accounts.config, value, now)?;
+        let payout = amount.min(value - fee);
+        require!(payout > 0, YieldVaultErrors::InsufficientFunds);
+
+        let shortfall = (payout + fee).saturating_sub(idle);
+        if shortfall > 0 {
+            let kamino_weight_bps = match active_protocol {
+                Protocol::Kamino => BPS_DENOMINATOR,
+                Protocol::Split => ctx.accounts.user_vault_account.kamino_weight_bps as u64,
+                Protocol::Marginfi | Protocol::None => 0,
+            };
+            let (kamino_part, marginfi_part) = split_shortfall(shortfall, kamino, marginfi, kamino_weight_bps)?;
+
+            let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+            if kamino_part > 0 {
+                let collateral_amount = if kamino_part >= kamino {
+                    collateral_held
+                } else {
+                    KaminoExchangeRate::load(&reserve)?.collateral_for_liquidity(kamino_part)?.min(collateral_held)
+                };
+                let before = ctx.accounts.user_usdc_vault_ata.amount;
+                ctx.accounts.kamino_cpi().redeem(signer, collateral_amount)?;
+                ctx.accounts.kamino_usdc_collateral_vault.reload()?;
+                ctx.accounts.user_usdc_vault_ata.reload()?;
+                emit!(RedeemedFromKamino {
+                    vault: ctx.accounts.user_vault_account.key(),
+                    collateral_amount,
+                    amount_received: ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before),
+                    fee: 0,
+                    slot: Clock::get()?.slot,
+                });
+            }
+            let marginfi_withdraw_all = marginfi_part > 0 && marginfi_part >= marginfi;
+            if marginfi_part > 0 {
+                let before = ctx.accounts.user_usdc_vault_ata.amount;
+                let vault_authority = ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info();
+                ctx.accounts.marginfi_cpi().withdraw(vault_authority, signer, marginfi_part, marginfi_withdraw_all)?;
+                ctx.accounts.user_usdc_vault_ata.reload()?;
+                emit!(RedeemedFromMarginfi {
+                    vault: ctx.accounts.user_vault_account.key(),
+                    amount_received: ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before),
+                    fee: 0,
+                    slot: Clock::get()?.slot,
+                });
+            }
+            // Positions emptied by this withdrawal are exited once the vault seeds are no longer borrowed
+            if kamino_part > 0 && ctx.accounts.kamino_usdc_collateral_vault.amount == 0 {
+                ctx.accounts.user_vault_account.exit_protocol(Protocol::Kamino);
+            }
+            if marginfi_withdraw_all {
+                ctx.accounts.user_vault_account.exit_protocol(Protocol::Marginfi);
+            }
+        }
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        pay_fee(
+            &ctx.accounts.token_program, &ctx.accounts.usdc_mint, &ctx.accounts.user_usdc_vault_ata,
+            ctx.accounts.user_vault_account.to_account_info(), &ctx.accounts.treasury_usdc_ta, signer, fee,
+        )?;
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+
+        // Redeems round down, so a withdrawal that empties the vault pays out what actually arrived
+        let balance = ctx.accounts.user_usdc_vault_ata.amount;
+        let payout = if ctx.accounts.user_vault_account.active_protocol == Protocol::None { payout.min(balance) } else { payout };
+        require!(payout > 0 && payout <= balance, YieldVaultErrors::InsufficientFunds);
+        let vault_withdraw_accounts = TransferChecked {
+            from: ctx.accounts.user_usdc_vault_ata.to_account_info(),
+            mint: ctx.accounts.usdc_mint.to_account_info(),
+            to: ctx.accounts.user_usdc_ta.to_account_info(),
+            authority: ctx.accounts.user_vault_account.to_account_info(),
+        };
+        let cpi_context = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), vault_withdraw_accounts, signer);
+        transfer_checked(cpi_context, payout, ctx.accounts.usdc_mint.decimals)?;
+        ctx.accounts.config.record_withdrawal(payout);
+        ctx.accounts.user_vault_account.record_withdrawal(payout)?;
+        msg!(
+            "Withdrawn {} USDC ({} redeemed, {} fee) from vault {} of owner {}",
+            payout, shortfall, fee, ctx.accounts.user_vault_account.key(), ctx.accounts.user.key().to_string()
         );
-    
+        emit!(Withdrawn {
+            vault: ctx.accounts.user_vault_account.key(),
+            owner: ctx.accounts.user.key(),
+            amount: payout,
+            principal: ctx.accounts.user_vault_account.deposited_amount,
+            protocol: ctx.accounts.user_vault_account.active_protocol,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // `min_out` is the least USDC value, at the bank's share value, the Marginfi deposit must gain.
+    pub fn deploy_usdc_marginfi(ctx: Context<DepositUsdcMarginfi>, amount: u64, min_out: u64) -> Result<()> {
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        ctx.accounts.config.require_not_paused(PAUSE_DEPLOYS)?;
+
+        ctx.accounts.registry.require_marginfi(&ctx.accounts.usdc_mint.key(), &ctx.accounts.marginfi_bank.key())?;
+        ctx.accounts.user_vault_account.enter_protocol(Protocol::Marginfi)?;
+        let value_before = ctx.accounts.marginfi_value()?;
+
+        // Marginfi CPI: Deposit USDC into the marginfi account
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()]; 
+        ctx.accounts.marginfi_cpi().deposit(signer, amount)?;
+        require!(ctx.accounts.marginfi_value()?.saturating_sub(value_before) >= min_out, YieldVaultErrors::SlippageExceeded);
+        emit!(DeployedToMarginfi {
+            vault: ctx.accounts.user_vault_account.key(),
+            amount,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Fails with SlippageExceeded unless at least `min_out` USDC reaches the vault ATA.
+    pub fn redeem_usdc_marginfi(ctx: Context<WithdrawUsdcMarginfi>, min_out: u64) -> Result<()> {
+        ctx.accounts.user_vault_account.require_protocol(Protocol::Marginfi)?;
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
+        // PDA seeds for the vault authority
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()]; 
+        let vault_authority = ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info();
         // amount=1 is a placehonder, as long the third argument enforces to withdraw all
-        mfi_cpi::lending_account_withdraw(cpi_ctx, 1, Some(true))?;
+        ctx.accounts.marginfi_cpi().withdraw(vault_authority, signer, 1, true)?;
+        ctx.accounts.user_vault_account.exit_protocol(Protocol::Marginfi);
 
         ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before);
+        require!(received >= min_out, YieldVaultErrors::SlippageExceeded);
+        // A split vault keeps its other leg; fees wait until the whole vault is back in the ATA
+        let fee = if ctx.accounts.user_vault_account.active_protocol == Protocol::None {
+            ctx.accounts.settle_fees(ctx.accounts.user_usdc_vault_ata.amount)?
+        } else {
+            0
+        };
         let amount = ctx.accounts.user_usdc_vault_ata.amount;
-        msg!("Withdrew {} USDC from vault {} of owner {}", amount, user_vault.key(), ctx.accounts.user.key().to_string());
+        msg!("Withdrew {} USDC from vault {} of owner {}", amount, ctx.accounts.user_vault_account.key(), ctx.accounts.user.key().to_string());
+        emit!(RedeemedFromMarginfi {
+            vault: ctx.accounts.user_vault_account.key(),
+            amount_received: received,
+            fee,
+            slot: Clock::get()?.slot,
+        });
         Ok(())
     }
 
-    pub fn deploy_usdc_kamino(ctx: Context<TransferUsdcKamino>,  amount: u64) -> Result<()> {
+    // Withdraws `amount` USDC from Marginfi and leaves the rest of the position deployed;
+    // use redeem_usdc_marginfi to close the whole balance.
+    // Fails with WithdrawalShortfall unless exactly `amount` USDC reaches the vault ATA.
+    pub fn withdraw_usdc_marginfi(ctx: Context<WithdrawUsdcMarginfi>, amount: u64) -> Result<()> {
         require!(amount > 0, YieldVaultErrors::InvalidAmount);
-        // CPI to deposit from our vault into Kamino
+        ctx.accounts.user_vault_account.require_protocol(Protocol::Marginfi)?;
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
         let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
-        let cpi_deposit_accounts = kamino_cpi::accounts::DepositReserveLiquidity {
-            owner:                          ctx.accounts.user_vault_account.to_account_info(),
-            reserve:                        ctx.accounts.kamino_reserve.to_account_info(),
-            // Lending Market accounts
-            lending_market:                 ctx.accounts.kamino_lending_market.to_account_info(),
-            lending_market_authority:       ctx.accounts.kamino_lending_market_authority.to_account_info(),
-            // Reserve accounts
-            reserve_liquidity_mint:         ctx.accounts.usdc_mint.to_account_info(),
-            reserve_liquidity_supply:       ctx.accounts.kamino_reserve_liquidity_supply.to_account_info(),
-            reserve_collateral_mint:        ctx.accounts.kamino_usdc_collateral_mint.to_account_info(), // TODO: check if this is correct
-            // User accounts
-            user_source_liquidity:          ctx.accounts.user_usdc_vault_ata.to_account_info(),
-            user_destination_collateral:    ctx.accounts.kamino_usdc_collateral_vault.to_account_info(),
-            // Token programs
-            collateral_token_program:       ctx.accounts.token_program.to_account_info(),
-            liquidity_token_program:        ctx.accounts.token_program.to_account_info(),
-            instruction_sysvar_account:     ctx.accounts.instruction_sysvar_account.to_account_info(),
-        };
+        let vault_authority = ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info();
+        ctx.accounts.marginfi_cpi().withdraw(vault_authority, signer, amount, false)?;
+        // Marginfi pays out the exact amount
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before);
+        require!(received >= amount, YieldVaultErrors::WithdrawalShortfall);
+        // Withdrawing the last of the balance closes the position like redeem_usdc_marginfi
+        let mut fee = 0;
+        if ctx.accounts.marginfi_value()? == 0 {
+            ctx.accounts.user_vault_account.exit_protocol(Protocol::Marginfi);
+            // A split vault keeps its other leg; fees wait until the whole vault is back in the ATA
+            if ctx.accounts.user_vault_account.active_protocol == Protocol::None {
+                fee = ctx.accounts.settle_fees(ctx.accounts.user_usdc_vault_ata.amount)?;
+            }
+        }
+        msg!("Withdrew {} USDC from Marginfi for user {}", amount, ctx.accounts.user.key().to_string());
+        emit!(RedeemedFromMarginfi {
+            vault: ctx.accounts.user_vault_account.key(),
+            amount_received: received,
+            fee,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
 
-        let cpi_ctx_kamino = CpiContext::new_with_signer(
-            ctx.accounts.kamino_program.to_account_info(),
-            cpi_deposit_accounts,
-            signer,
-        );
-        kamino_cpi::deposit_reserve_liquidity(cpi_ctx_kamino, amount)?;
+    // `min_out` is the least USDC value, at the post-deposit reserve exchange rate, the minted kUSDC must be worth.
+    pub fn deploy_usdc_kamino(ctx: Context<TransferUsdcKamino>,  amount: u64, min_out: u64) -> Result<()> {
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        ctx.accounts.config.require_not_paused(PAUSE_DEPLOYS)?;
+        ctx.accounts.registry.require_kamino(&ctx.accounts.usdc_mint.key(), &ctx.accounts.kamino_reserve.key())?;
+        ctx.accounts.user_vault_account.enter_protocol(Protocol::Kamino)?;
+        let collateral_before = ctx.accounts.kamino_usdc_collateral_vault.amount;
+        // CPI to deposit from our vault into Kamino
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        ctx.accounts.kamino_cpi().deposit(signer, amount)?;
         msg!("Deposited {} USDC to KLend for user {}",
         amount, ctx.accounts.user.key().to_string());
         ctx.accounts.kamino_usdc_collateral_vault.reload()?;
         let collateral_amount =ctx.accounts.kamino_usdc_collateral_vault.amount;
+        let collateral_received = collateral_amount.saturating_sub(collateral_before);
+        let rate = KaminoExchangeRate::load(&ctx.accounts.kamino_reserve.to_account_info())?;
+        require!(rate.liquidity_for_collateral(collateral_received)? >= min_out, YieldVaultErrors::SlippageExceeded);
         msg!("Reserved {} kUSDC at {} user collateral vault ATA", collateral_amount, ctx.accounts.kamino_usdc_collateral_vault.key().to_string());
+        emit!(DeployedToKamino {
+            vault: ctx.accounts.user_vault_account.key(),
+            amount,
+            collateral_received,
+            slot: Clock::get()?.slot,
+        });
         Ok(())
     }
 
-    // TODO: Treat the parameter as desired USDC - need to convert it to collateral using the reserve exchange rate on-chain (requires reading reserve state/slot math)
-    pub fn redeem_usdc_kaminio(ctx: Context<TransferUsdcKamino>) -> Result<()> {
+    // Redeems the whole kUSDC balance; see redeem_usdc_kamino for partial, USDC-denominated redemptions.
+    // Fails with SlippageExceeded unless at least `min_out` USDC reaches the vault ATA.
+    pub fn redeem_usdc_kaminio(ctx: Context<TransferUsdcKamino>, min_out: u64) -> Result<()> {
+        ctx.accounts.user_vault_account.require_protocol(Protocol::Kamino)?;
         let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
         // reload the collateral vault full balance
         ctx.accounts.kamino_usdc_collateral_vault.reload()?;
         let collateral_amount =ctx.accounts.kamino_usdc_collateral_vault.amount;
         require!(collateral_amount > 0, YieldVaultErrors::NothingRedeemed);
-        let cpi_accounts_kamino = kamino_cpi::accounts::RedeemReserveCollateral {
-            owner:                         ctx.accounts.user_vault_account.to_account_info(), // PDA
-            reserve:                       ctx.accounts.kamino_reserve.to_account_info(),
-            lending_market:                ctx.accounts.kamino_lending_market.to_account_info(),
-            lending_market_authority:      ctx.accounts.kamino_lending_market_authority.to_account_info(),
-            // Reserve accounts
-            reserve_liquidity_mint:        ctx.accounts.usdc_mint.to_account_info(),
-            reserve_liquidity_supply:      ctx.accounts.kamino_reserve_liquidity_supply.to_account_info(),
-            reserve_collateral_mint:       ctx.accounts.kamino_usdc_collateral_mint.to_account_info(),
-            // User accounts
-            user_source_collateral:        ctx.accounts.kamino_usdc_collateral_vault.to_account_info(),
-            user_destination_liquidity:    ctx.accounts.user_usdc_vault_ata.to_account_info(),
-
-            collateral_token_program:      ctx.accounts.token_program.to_account_info(),
-            liquidity_token_program:       ctx.accounts.token_program.to_account_info(),
-            instruction_sysvar_account:    ctx.accounts.instruction_sysvar_account.to_account_info(),
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
+        ctx.accounts.kamino_cpi().redeem(signer, collateral_amount)?;
+        ctx.accounts.user_vault_account.exit_protocol(Protocol::Kamino);
+
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before);
+        require!(received >= min_out, YieldVaultErrors::SlippageExceeded);
+        // A split vault keeps its other leg; fees wait until the whole vault is back in the ATA
+        let fee = if ctx.accounts.user_vault_account.active_protocol == Protocol::None {
+            ctx.accounts.settle_fees(ctx.accounts.user_usdc_vault_ata.amount)?
+        } else {
+            0
         };
-        let cpi_ctx_kamino = CpiContext::new_with_signer(
-            ctx.accounts.kamino_program.to_account_info(),
-            cpi_accounts_kamino,
-            signer,
-        );
-        kamino_cpi::redeem_reserve_collateral(cpi_ctx_kamino, collateral_amount)?;
         msg!("Withdrew {} USDC from KLend for user {}", collateral_amount, ctx.accounts.user.key().to_string());
+        emit!(RedeemedFromKamino {
+            vault: ctx.accounts.user_vault_account.key(),
+            collateral_amount,
+            amount_received: received,
+            fee,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Redeems enough kUSDC to return `amount_usdc` USDC to the vault ATA, converting through
+    // the reserve exchange rate. The reserve must be refreshed earlier in the same transaction.
+    // Fails with SlippageExceeded unless at least `min_out` USDC reaches the vault ATA.
+    pub fn redeem_usdc_kamino(ctx: Context<TransferUsdcKamino>, amount_usdc: u64, min_out: u64) -> Result<()> {
+        require!(amount_usdc > 0, YieldVaultErrors::InvalidAmount);
+        ctx.accounts.user_vault_account.require_protocol(Protocol::Kamino)?;
+
+        let rate = KaminoExchangeRate::load(&ctx.accounts.kamino_reserve.to_account_info())?;
+        let held = ctx.accounts.kamino_usdc_collateral_vault.amount;
+        let collateral_amount = rate.collateral_for_liquidity(amount_usdc)?.min(held);
+        require!(collateral_amount > 0, YieldVaultErrors::NothingRedeemed);
+
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        ctx.accounts.kamino_cpi().redeem(signer, collateral_amount)?;
+
+        ctx.accounts.kamino_usdc_collateral_vault.reload()?;
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before);
+        require!(received >= min_out, YieldVaultErrors::SlippageExceeded);
+        // Redeeming the last of the kUSDC closes the position like redeem_usdc_kaminio
+        let mut fee = 0;
+        if ctx.accounts.kamino_usdc_collateral_vault.amount == 0 {
+            ctx.accounts.user_vault_account.exit_protocol(Protocol::Kamino);
+            // A split vault keeps its other leg; fees wait until the whole vault is back in the ATA
+            if ctx.accounts.user_vault_account.active_protocol == Protocol::None {
+                fee = ctx.accounts.settle_fees(ctx.accounts.user_usdc_vault_ata.amount)?;
+            }
+        }
+        msg!("Redeemed {} kUSDC for ~{} USDC from KLend for user {}", collateral_amount, amount_usdc, ctx.accounts.user.key().to_string());
+        emit!(RedeemedFromKamino {
+            vault: ctx.accounts.user_vault_account.key(),
+            collateral_amount,
+            amount_received: received,
+            fee,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Moves a vault's position from one protocol to the other in a single instruction:
+    // everything redeemed from `from` is deposited into `to`, so nothing is left idle
+    // if the keeper stops between steps. A Split vault is drained from both legs;
+    // moving into a split is done with `allocate`. Fails with SlippageExceeded unless the
+    // position landed in `to`, plus the fee taken on the way, is worth at least `min_out`.
+    pub fn rebalance(ctx: Context<Rebalance>, from: Protocol, to: Protocol, min_out: u64) -> Result<()> {
+        require!(
+            from != Protocol::None && to != Protocol::None && to != Protocol::Split && from != to,
+            YieldVaultErrors::IncorrectProtocol
+        );
+        ctx.accounts.config.require_not_paused(PAUSE_REBALANCES)?;
+        // The whole vault moves, so fees can be settled on what comes back
+        require!(ctx.accounts.user_vault_account.active_protocol == from, YieldVaultErrors::IncorrectProtocol);
+
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
+        if from.includes(Protocol::Kamino) {
+            let collateral_amount = ctx.accounts.kamino_usdc_collateral_vault.amount;
+            require!(collateral_amount > 0 || from == Protocol::Split, YieldVaultErrors::NothingRedeemed);
+            if collateral_amount > 0 {
+                ctx.accounts.kamino_cpi().redeem(signer, collateral_amount)?;
+            }
+        }
+        if from.includes(Protocol::Marginfi) {
+            let vault_authority = ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info();
+            ctx.accounts.marginfi_cpi().withdraw(vault_authority, signer, 1, true)?;
+        }
+
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.checked_sub(before).ok_or(YieldVaultErrors::MathOverflow)?;
+        require!(received > 0, YieldVaultErrors::NothingRedeemed);
+        // Yield is realized here, so fees are taken before the proceeds are redeployed
+        let fee = ctx.accounts.settle_fees(ctx.accounts.user_usdc_vault_ata.amount)?;
+        let deployed = received.saturating_sub(fee);
+        require!(deployed > 0, YieldVaultErrors::NothingRedeemed);
+
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        let collateral_before = ctx.accounts.kamino_usdc_collateral_vault.amount;
+        let usdc_mint = ctx.accounts.usdc_mint.key();
+        match to {
+            Protocol::Kamino => {
+                ctx.accounts.registry.require_kamino(&usdc_mint, &ctx.accounts.kamino_reserve.key())?;
+                ctx.accounts.kamino_cpi().deposit(signer, deployed)?
+            }
+            Protocol::Marginfi => {
+                ctx.accounts.registry.require_marginfi(&usdc_mint, &ctx.accounts.marginfi_bank.key())?;
+                ctx.accounts.marginfi_cpi().deposit(signer, deployed)?
+            }
+            Protocol::None | Protocol::Split => return err!(YieldVaultErrors::IncorrectProtocol),
+        }
+        ctx.accounts.user_vault_account.active_protocol = to;
+        ctx.accounts.user_vault_account.kamino_weight_bps = if to == Protocol::Kamino { BPS_DENOMINATOR as u16 } else { 0 };
+        ctx.accounts.kamino_usdc_collateral_vault.reload()?;
+        // `from` was emptied, so the position in `to` is all that landed; the fee counts towards `min_out`
+        let landed = match to {
+            Protocol::Kamino => KaminoExchangeRate::load(&ctx.accounts.kamino_reserve.to_account_info())?
+                .liquidity_for_collateral(ctx.accounts.kamino_usdc_collateral_vault.amount)?,
+            _ => ctx.accounts.marginfi_value()?,
+        };
+        require!(landed.saturating_add(fee) >= min_out, YieldVaultErrors::SlippageExceeded);
+        msg!("Rebalanced {} USDC of vault {} from {:?} to {:?}", deployed, ctx.accounts.user_vault_account.key(), from, to);
+        emit!(Rebalanced {
+            vault: ctx.accounts.user_vault_account.key(),
+            from,
+            to,
+            amount_redeemed: received,
+            fee,
+            amount_deployed: deployed,
+            collateral_received: ctx.accounts.kamino_usdc_collateral_vault.amount.saturating_sub(collateral_before),
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Moves a vault towards `kamino_weight_bps` of its funds in Kamino and the rest in Marginfi,
+    // counting idle USDC in the vault ATA. Only the difference to the targets is redeemed and
+    // redeployed. Positions are valued on-chain against the registry's reserve and bank, so a vault
+    // holding kUSDC needs the reserve refreshed earlier in the same transaction. Nothing leaves the vault, so fees are not settled here: they are
+    // assessed against the vault's whole value before any payout and on full redeems.
+    // Fails with SlippageExceeded unless the vault is worth at least `min_out` afterwards.
+    pub fn allocate(ctx: Context<Rebalance>, kamino_weight_bps: u16, min_out: u64) -> Result<()> {
+        require!(kamino_weight_bps as u64 <= BPS_DENOMINATOR, YieldVaultErrors::InvalidWeight);
+        ctx.accounts.config.require_not_paused(PAUSE_REBALANCES)?;
+        ctx.accounts.config.require_not_paused(PAUSE_DEPLOYS)?;
+
+        // Positions, targets and the `min_out` check are all valued off these, so they must be the registry's
+        let usdc_mint = ctx.accounts.usdc_mint.key();
+        let reserve = ctx.accounts.kamino_reserve.to_account_info();
+        ctx.accounts.registry.require_kamino(&usdc_mint, &reserve.key())?;
+        check_kamino_reserve(&reserve, &usdc_mint, &ctx.accounts.kamino_usdc_collateral_mint.key())?;
+        ctx.accounts.registry.require_marginfi(&usdc_mint, &ctx.accounts.marginfi_bank.key())?;
+        let collateral_held = ctx.accounts.kamino_usdc_collateral_vault.amount;
+        let kamino = if collateral_held > 0 {
+            ctx.accounts.kamino_cpi().require_refreshed()?;
+            KaminoExchangeRate::load(&reserve)?.liquidity_for_collateral(collateral_held)?
+        } else {
+            0
+        };
+        let marginfi = ctx.accounts.marginfi_value()?;
+        let idle = ctx.accounts.user_usdc_vault_ata.amount;
+        let total = idle
+            .checked_add(kamino)
+            .and_then(|v| v.checked_add(marginfi))
+            .ok_or(YieldVaultErrors::MathOverflow)?;
+        let kamino_target = mul_div(total, kamino_weight_bps as u64, BPS_DENOMINATOR)?;
+        let marginfi_target = total - kamino_target;
+
+        // Pull the excess out first so it can fund the other side
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        if kamino > kamino_target {
+            let collateral_amount = if kamino_target == 0 {
+                collateral_held
+            } else {
+                KaminoExchangeRate::load(&reserve)?.collateral_for_liquidity(kamino - kamino_target)?.min(collateral_held)
+            };
+            if collateral_amount > 0 {
+                ctx.accounts.kamino_cpi().redeem(signer, collateral_amount)?;
+            }
+        }
+        if marginfi > marginfi_target {
+            let vault_authority = ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info();
+            ctx.accounts.marginfi_cpi().withdraw(vault_authority, signer, marginfi - marginfi_target, marginfi_target == 0)?;
+        }
+
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let mut available = ctx.accounts.user_usdc_vault_ata.amount;
+        if kamino_target > kamino {
+            let amount = (kamino_target - kamino).min(available);
+            if amount > 0 {
+                ctx.accounts.kamino_cpi().deposit(signer, amount)?;
+                available -= amount;
+            }
+        }
+        if marginfi_target > marginfi {
+            let amount = (marginfi_target - marginfi).min(available);
+            if amount > 0 {
+                ctx.accounts.marginfi_cpi().deposit(signer, amount)?;
+            }
+        }
+
+        ctx.accounts.kamino_usdc_collateral_vault.reload()?;
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let collateral_after = ctx.accounts.kamino_usdc_collateral_vault.amount;
+        let kamino_after = KaminoExchangeRate::load(&reserve)?.liquidity_for_collateral(collateral_after)?;
+        let marginfi_after = ctx.accounts.marginfi_value()?;
+        let total_after = ctx.accounts.user_usdc_vault_ata.amount
+            .checked_add(kamino_after)
+            .and_then(|v| v.checked_add(marginfi_after))
+            .ok_or(YieldVaultErrors::MathOverflow)?;
+        require!(total_after >= min_out, YieldVaultErrors::SlippageExceeded);
+        let user_vault = &mut ctx.accounts.user_vault_account;
+        user_vault.set_holdings(collateral_after > 0, marginfi_after > 0)?;
+        user_vault.kamino_weight_bps = kamino_weight_bps;
+        msg!(
+            "Allocated vault {} to {} bps Kamino: {} USDC total, Kamino {} -> {}, Marginfi {} -> {}",
+            user_vault.key(), kamino_weight_bps, total, kamino, kamino_target, marginfi, marginfi_target
+        );
+        emit!(Allocated {
+            vault: user_vault.key(),
+            kamino_weight_bps,
+            total,
+            kamino_before: kamino,
+            marginfi_before: marginfi,
+            kamino_target,
+            marginfi_target,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Grows a UserVault created with the baseline layout (before `active_protocol` / fee tracking / multi-mint,
+    // layout version 0) to the current one, with the keeper stating where the funds currently sit;
+    // a non-empty kUSDC ATA must be reported as Kamino. `migrate_vault` does the same from the vault's holdings.
+    pub fn migrate_user_vault(ctx: Context<MigrateUserVault>, active_protocol: Protocol) -> Result<()> {
+        if ctx.accounts.kamino_usdc_collateral_vault.amount > 0 {
+            require!(active_protocol.includes(Protocol::Kamino), YieldVaultErrors::IncorrectProtocol);
+        }
+        let kamino_weight_bps = match active_protocol {
+            Protocol::Kamino => BPS_DENOMINATOR as u16,
+            Protocol::Split => (BPS_DENOMINATOR / 2) as u16,
+            _ => 0,
+        };
+        let vault_info = ctx.accounts.user_vault_account.to_account_info();
+        ctx.accounts.upgrade_vault(&vault_info, |_| Ok((active_protocol, kamino_weight_bps)))
+    }
+
+    // Upgrades a vault with the baseline layout to `UserVault::VERSION` like `migrate_user_vault`, taking
+    // `active_protocol` and `kamino_weight_bps` from where the vault holds funds. Keeper-only (the keeper pays
+    // the rent top-up) and needs no input, so the keeper batches it over every baseline vault.
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let vault_info = ctx.accounts.user_vault_account.to_account_info();
+        ctx.accounts.upgrade_vault(&vault_info, |value| {
+            let deployed = value.kamino.checked_add(value.marginfi).ok_or(YieldVaultErrors::MathOverflow)?;
+            Ok(match (value.kamino > 0, value.marginfi > 0) {
+                (true, true) => (Protocol::Split, mul_div(value.kamino, BPS_DENOMINATOR, deployed)? as u16),
+                (true, false) => (Protocol::Kamino, BPS_DENOMINATOR as u16),
+                (false, true) => (Protocol::Marginfi, 0),
+                (false, false) => (Protocol::None, 0),
+            })
+        })
+    }
+
+    // -------- User escape hatch --------
+    // Owner-signed redeems so funds are not stuck in a protocol while the keeper is down.
+    // With a non-zero `emergency_timeout` they only open once the keeper has missed its heartbeat for that long.
+
+    pub fn emergency_redeem_kamino(ctx: Context<EmergencyRedeemKamino>) -> Result<()> {
+        ctx.accounts.config.require_keeper_inactive(Clock::get()?.unix_timestamp)?;
+        ctx.accounts.user_vault_account.require_protocol(Protocol::Kamino)?;
+        let collateral_amount = ctx.accounts.kamino_usdc_collateral_vault.amount;
+        require!(collateral_amount > 0, YieldVaultErrors::NothingRedeemed);
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        ctx.accounts.kamino_cpi().redeem(signer, collateral_amount)?;
+        ctx.accounts.user_vault_account.exit_protocol(Protocol::Kamino);
+
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before);
+        // A split vault keeps its other leg; fees wait until the whole vault is back in the ATA
+        let fee = if ctx.accounts.user_vault_account.active_protocol == Protocol::None {
+            ctx.accounts.settle_fees(ctx.accounts.user_usdc_vault_ata.amount)?
+        } else {
+            0
+        };
+        msg!("Emergency redeemed {} kUSDC from KLend by owner {}", collateral_amount, ctx.accounts.user.key());
+        emit!(RedeemedFromKamino {
+            vault: ctx.accounts.user_vault_account.key(),
+            collateral_amount,
+            amount_received: received,
+            fee,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    pub fn emergency_redeem_marginfi(ctx: Context<EmergencyRedeemMarginfi>) -> Result<()> {
+        ctx.accounts.config.require_keeper_inactive(Clock::get()?.unix_timestamp)?;
+        ctx.accounts.user_vault_account.require_protocol(Protocol::Marginfi)?;
+        let before = ctx.accounts.user_usdc_vault_ata.amount;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        let vault_authority = ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info();
+        ctx.accounts.marginfi_cpi().withdraw(vault_authority, signer, 1, true)?;
+        ctx.accounts.user_vault_account.exit_protocol(Protocol::Marginfi);
+
+        ctx.accounts.user_usdc_vault_ata.reload()?;
+        let received = ctx.accounts.user_usdc_vault_ata.amount.saturating_sub(before);
+        // A split vault keeps its other leg; fees wait until the whole vault is back in the ATA
+        let fee = if ctx.accounts.user_vault_account.active_protocol == Protocol::None {
+            ctx.accounts.settle_fees(ctx.accounts.user_usdc_vault_ata.amount)?
+        } else {
+            0
+        };
+        msg!("Emergency withdrew {} USDC from Marginfi by owner {}", ctx.accounts.user_usdc_vault_ata.amount, ctx.accounts.user.key());
+        emit!(RedeemedFromMarginfi {
+            vault: ctx.accounts.user_vault_account.key(),
+            amount_received: received,
+            fee,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Called by the keeper every tick; the escape hatch timeout is measured from the last heartbeat.
+    pub fn keeper_heartbeat(ctx: Context<KeeperHeartbeat>) -> Result<()> {
+        ctx.accounts.config.last_keeper_heartbeat = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // Tears down an empty vault: closes both vault ATAs and the Marginfi account, then the
+    // UserVault itself, returning all rent to the owner. Marginfi rejects the close while the
+    // account still holds a balance, so a vault with funds in either protocol cannot be closed.
+    pub fn close_vault(ctx: Context<CloseVault>) -> Result<()> {
+        let user_vault = &ctx.accounts.user_vault_account;
+        require!(user_vault.active_protocol == Protocol::None, YieldVaultErrors::FundsStillDeployed);
+        require!(
+            ctx.accounts.user_usdc_vault_ata.amount == 0 && ctx.accounts.kamino_usdc_collateral_vault.amount == 0,
+            YieldVaultErrors::VaultNotEmpty
+        );
+        let signer: &[&[&[u8]]] = &[&user_vault.seeds()];
+
+        for (token_account, token_program) in [
+            (ctx.accounts.user_usdc_vault_ata.to_account_info(), ctx.accounts.token_program.to_account_info()),
+            (ctx.accounts.kamino_usdc_collateral_vault.to_account_info(), ctx.accounts.collateral_token_program.to_account_info()),
+        ] {
+            let close_accounts = CloseAccount {
+                account: token_account,
+                destination: ctx.accounts.user.to_account_info(),
+                authority: user_vault.to_account_info(),
+            };
+            close_account(CpiContext::new_with_signer(token_program, close_accounts, signer))?;
+        }
+
+        // Marginfi CPI: close the vault's marginfi account, rent goes to the fee payer (owner)
+        let cpi_accounts = mfi_accounts::MarginfiAccountClose {
+            marginfi_account: ctx.accounts.marginfi_account.to_account_info(),
+            authority:        user_vault.to_account_info(),
+            fee_payer:        ctx.accounts.user.to_account_info(),
+        };
+        mfi_cpi::marginfi_account_close(CpiContext::new_with_signer(
+            ctx.accounts.marginfi_program.to_account_info(), cpi_accounts, signer
+        ))?;
+
+        // Principal still on the books (e.g. after a protocol loss) no longer counts towards the global cap
+        ctx.accounts.config.record_withdrawal(user_vault.deposited_amount);
+        msg!("Closed vault {} of owner {}", user_vault.key(), ctx.accounts.user.key().to_string());
+        emit!(VaultClosed {
+            vault: user_vault.key(),
+            owner: ctx.accounts.user.key(),
+            marginfi_account: user_vault.marginfi_account,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Read-only valuation of a vault, meant to be simulated: the Position is handed back through
+    // `set_return_data`. kUSDC is converted at the reserve exchange rate and Marginfi asset shares
+    // at the bank's `asset_share_value`, so values are as fresh as the last reserve refresh / bank accrual.
+    pub fn get_position(ctx: Context<GetPosition>) -> Result<Position> {
+        let usdc_mint = ctx.accounts.usdc_mint.key();
+        check_kamino_reserve(&ctx.accounts.kamino_reserve.to_account_info(), &usdc_mint, &ctx.accounts.kamino_usdc_collateral_mint.key())?;
+        check_marginfi_bank(&ctx.accounts.marginfi_bank.to_account_info(), &usdc_mint)?;
+        let rate = KaminoExchangeRate::load(&ctx.accounts.kamino_reserve.to_account_info())?;
+        let idle = ctx.accounts.user_usdc_vault_ata.amount;
+        let kamino = rate.liquidity_for_collateral(ctx.accounts.kamino_usdc_collateral_vault.amount)?;
+        let marginfi = marginfi_asset_value(&ctx.accounts.marginfi_account.to_account_info(), &ctx.accounts.marginfi_bank.to_account_info())?;
+        let total = idle
+            .checked_add(kamino)
+            .and_then(|v| v.checked_add(marginfi))
+            .ok_or(YieldVaultErrors::MathOverflow)?;
+        msg!("Vault {} holds {} USDC: idle {}, Kamino {}, Marginfi {}", ctx.accounts.user_vault_account.key(), total, idle, kamino, marginfi);
+        Ok(Position { idle, kamino, marginfi, total })
+    }
+
+    // -------- Program config (admin + keeper set) --------
+
+    // One-time setup, signed by the bootstrap KEEPER_PUBKEY, which becomes the first keeper.
+    pub fn initialize_config(ctx: Context<InitializeConfig>, admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.bump = ctx.bumps.config;
+        config.admin = admin;
+        config.pending_admin = None;
+        config.keepers = vec![ctx.accounts.payer.key()];
+        config.guardian = admin;
+        config.paused = 0;
+        config.treasury = admin;
+        config.management_fee_bps = 0;
+        config.performance_fee_bps = 0;
+        config.max_vault_deposit = 0;
+        config.max_total_deposits = 0;
+        config.total_deposits = 0;
+        config.emergency_timeout = 0;
+        config.last_keeper_heartbeat = Clock::get()?.unix_timestamp;
+        config.timelock_delay = 0;
+        config.next_change_id = 0;
+        config.pending_changes = vec![];
+        msg!("Config initialized with admin {} and keeper {}", admin, ctx.accounts.payer.key());
+        Ok(())
+    }
+
+    // Step 1 of the admin transfer: the current admin nominates a successor.
+    pub fn propose_admin(ctx: Context<UpdateConfig>, new_admin: Pubkey) -> Result<()> {
+        ctx.accounts.config.pending_admin = Some(new_admin);
+        msg!("Admin transfer to {} proposed", new_admin);
+        Ok(())
+    }
+
+    // Step 2: the nominee signs to take over, so a typo in propose_admin cannot brick the config.
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.pending_admin.key();
+        config.pending_admin = None;
+        msg!("Admin transferred to {}", config.admin);
+        Ok(())
+    }
+
+    // Keepers, guardian, fees, treasury, deposit caps, timeouts and registry markets change through a timelock:
+    // the admin proposes, anyone executes once `timelock_delay` has passed, and the admin can
+    // cancel until then. Pending changes are stored in the config, so anyone can inspect them.
+    pub fn propose_config_change(ctx: Context<UpdateConfig>, change: ConfigChange) -> Result<()> {
+        change.validate()?;
+        let now = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        require!(config.pending_changes.len() < VaultConfig::MAX_PENDING_CHANGES, YieldVaultErrors::TooManyPendingChanges);
+        let id = config.next_change_id;
+        let eta = now.checked_add(config.timelock_delay).ok_or(YieldVaultErrors::MathOverflow)?;
+        config.next_change_id = id.checked_add(1).ok_or(YieldVaultErrors::MathOverflow)?;
+        config.pending_changes.push(PendingChange { id, change, eta });
+        msg!("Config change {} proposed: {:?}, executable at {}", id, change, eta);
+        Ok(())
+    }
+
+    // Permissionless once the delay has passed; the change is re-validated against the current config.
+    pub fn execute_config_change(ctx: Context<ExecuteConfigChange>, id: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        let index = config.pending_change_index(id)?;
+        let pending = config.pending_changes[index];
+        require!(now >= pending.eta, YieldVaultErrors::TimelockNotExpired);
+        config.pending_changes.remove(index);
+        match pending.change {
+            ConfigChange::SetMarkets { .. } | ConfigChange::RemoveMarkets { .. } => {
+                let registry = ctx.accounts.registry.as_mut().ok_or(YieldVaultErrors::RegistryRequired)?;
+                registry.apply(pending.change)?;
+            }
+            change => config.apply(change)?,
+        }
+        msg!("Config change {} executed", id);
+        Ok(())
+    }
+
+    pub fn cancel_config_change(ctx: Context<UpdateConfig>, id: u64) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let index = config.pending_change_index(id)?;
+        config.pending_changes.remove(index);
+        msg!("Config change {} cancelled", id);
+        Ok(())
+    }
+
+    // Creates the empty ProtocolRegistry; markets are then added with SetMarkets config changes.
+    pub fn initialize_registry(ctx: Context<InitializeRegistry>) -> Result<()> {
+        let registry = &mut ctx.accounts.registry;
+        registry.bump = ctx.bumps.registry;
+        registry.entries = vec![];
+        msg!("Protocol registry initialized");
+        Ok(())
+    }
+
+    // Emergency switch: `paused` is a bitmask of PAUSE_DEPOSITS | PAUSE_DEPLOYS | PAUSE_REBALANCES.
+    // Withdrawals and protocol redeems are never paused, so funds can always be pulled out.
+    pub fn set_pause(ctx: Context<SetPause>, paused: u8) -> Result<()> {
+        require!(paused & !PAUSE_ALL == 0, YieldVaultErrors::InvalidPauseFlags);
+        ctx.accounts.config.paused = paused;
+        msg!("Pause flags set to {:#05b} by {}", paused, ctx.accounts.authority.key());
+        Ok(())
+    }
+
+    // -------- Pooled vault (vyUSDC shares) --------
+    // One VaultState per USDC mint holds every pooled depositor's funds, so the keeper
+    // moves the whole pool with a single transaction instead of one per user vault.
+
+    pub fn initialize_pool(ctx: Context<InitializePool>) -> Result<()> {
+        let vault_state = &mut ctx.accounts.vault_state;
+        vault_state.bump = ctx.bumps.vault_state;
+        vault_state.usdc_mint = ctx.accounts.usdc_mint.key();
+        vault_state.share_mint = ctx.accounts.share_mint.key();
+        vault_state.marginfi_account = ctx.accounts.marginfi_account.key();
+        vault_state.total_assets = 0;
+        vault_state.kamino_deployed = 0;
+        vault_state.marginfi_deployed = 0;
+        vault_state.fee_checkpoint = Clock::get()?.unix_timestamp;
+
+        // Marginfi CPI: Initialize the pool's marginfi account
+        let cpi_accounts = mfi_accounts::MarginfiAccountInitialize {
+            marginfi_group:   ctx.accounts.marginfi_group.to_account_info(),
+            marginfi_account: ctx.accounts.marginfi_account.to_account_info(),
+            authority:        vault_state.to_account_info(),
+            fee_payer:        ctx.accounts.keeper.to_account_info(),
+            system_program:   ctx.accounts.system_program.to_account_info(),
+        };
+        let signer: &[&[&[u8]]] = &[&vault_state.seeds()];
+        mfi_cpi::marginfi_account_initialize(CpiContext::new_with_signer(
+            ctx.accounts.marginfi_program.to_account_info(), cpi_accounts, signer
+        ))?;
+
+        msg!("Pool initialized for mint {} with share mint {}", vault_state.usdc_mint, vault_state.share_mint);
+        Ok(())
+    }
+
+    // Shares are priced off the pool's live value (see `pool_value`), after fees on it are settled,
+    // so a deposit never buys into yield the pool has earned but not yet booked.
+    pub fn deposit_shares(ctx: Context<TransferShares>, amount: u64) -> Result<()> {
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        ctx.accounts.config.require_not_paused(PAUSE_DEPOSITS)?;
+        let value = ctx.accounts.pool_value()?;
+        let now = Clock::get()?.unix_timestamp;
+        let fee = ctx.accounts.vault_state.settle_fees(&ctx.accounts.config, value.total, now)?;
+        let before = ctx.accounts.pool_usdc_ata.amount;
+        let deposit_accounts = TransferChecked {
+            from: ctx.accounts.user_usdc_ta.to_account_info(),
+            mint: ctx.accounts.usdc_mint.to_account_info(),
+            to: ctx.accounts.pool_usdc_ata.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        transfer_checked(CpiContext::new(ctx.accounts.token_program.to_account_info(), deposit_accounts), amount, ctx.accounts.usdc_mint.decimals)?;
+
+        // Shares are priced on what the pool actually received (net of any Token-2022 transfer fee).
+        ctx.accounts.pool_usdc_ata.reload()?;
+        let received = ctx.accounts.pool_usdc_ata.amount.checked_sub(before).ok_or(YieldVaultErrors::MathOverflow)?;
+        ctx.accounts.config.record_deposit(None, received)?;
+        let shares = ctx.accounts.vault_state.shares_for_assets(received, ctx.accounts.share_mint.supply)?;
+        require!(shares > 0, YieldVaultErrors::InvalidAmount);
+
+        ctx.accounts.raise_idle(&value, fee)?;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.vault_state.seeds()];
+        pay_fee(
+            &ctx.accounts.token_program, &ctx.accounts.usdc_mint, &ctx.accounts.pool_usdc_ata,
+            ctx.accounts.vault_state.to_account_info(), &ctx.accounts.treasury_usdc_ta, signer, fee,
+        )?;
+        let mint_accounts = MintTo {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            to: ctx.accounts.user_share_ta.to_account_info(),
+            authority: ctx.accounts.vault_state.to_account_info(),
+        };
+        mint_to(CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), mint_accounts, signer), shares)?;
+
+        let vault_state = &mut ctx.accounts.vault_state;
+        vault_state.total_assets = vault_state.total_assets.checked_add(received).ok_or(YieldVaultErrors::MathOverflow)?;
+        msg!("Deposited {} USDC to pool, minted {} shares to {}", received, shares, ctx.accounts.user.key());
+        Ok(())
+    }
+
+    // Burns shares for their part of the pool's live value after fees. Whatever idle USDC can't cover
+    // is redeemed from Kamino/Marginfi in the same instruction, so no keeper unwind is needed first.
+    pub fn withdraw_shares(ctx: Context<TransferShares>, shares: u64) -> Result<()> {
+        require!(shares > 0, YieldVaultErrors::InvalidAmount);
+        let value = ctx.accounts.pool_value()?;
+        let now = Clock::get()?.unix_timestamp;
+        let fee = ctx.accounts.vault_state.settle_fees(&ctx.accounts.config, value.total, now)?;
+        let amount = ctx.accounts.vault_state.assets_for_shares(shares, ctx.accounts.share_mint.supply)?;
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+
+        let needed = amount.checked_add(fee).ok_or(YieldVaultErrors::MathOverflow)?;
+        ctx.accounts.raise_idle(&value, needed)?;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.vault_state.seeds()];
+        pay_fee(
+            &ctx.accounts.token_program, &ctx.accounts.usdc_mint, &ctx.accounts.pool_usdc_ata,
+            ctx.accounts.vault_state.to_account_info(), &ctx.accounts.treasury_usdc_ta, signer, fee,
+        )?;
+        ctx.accounts.pool_usdc_ata.reload()?;
+        // Redeems round down, so a withdrawal that empties a position pays out what actually arrived;
+        // anything short of that rounding fails rather than burning shares that are not paid out
+        let available = ctx.accounts.pool_usdc_ata.amount;
+        require!(available.saturating_add(REDEEM_ROUNDING_TOLERANCE) >= amount, YieldVaultErrors::InsufficientFunds);
+        let amount = amount.min(available);
+        require!(amount > 0, YieldVaultErrors::InsufficientFunds);
+
+        let burn_accounts = Burn {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            from: ctx.accounts.user_share_ta.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        burn(CpiContext::new(ctx.accounts.token_program.to_account_info(), burn_accounts), shares)?;
+
+        let withdraw_accounts = TransferChecked {
+            from: ctx.accounts.pool_usdc_ata.to_account_info(),
+            mint: ctx.accounts.usdc_mint.to_account_info(),
+            to: ctx.accounts.user_usdc_ta.to_account_info(),
+            authority: ctx.accounts.vault_state.to_account_info(),
+        };
+        transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), withdraw_accounts, signer), amount, ctx.accounts.usdc_mint.decimals)?;
+
+        ctx.accounts.config.record_withdrawal(amount);
+        let vault_state = &mut ctx.accounts.vault_state;
+        vault_state.total_assets = vault_state.total_assets.checked_sub(amount).ok_or(YieldVaultErrors::MathOverflow)?;
+        msg!("Burned {} shares of {}, withdrawn {} USDC from pool", shares, ctx.accounts.user.key(), amount);
+        Ok(())
+    }
+
+    // `min_out` is the least USDC value, at the post-deposit reserve exchange rate, the minted kUSDC must be worth.
+    pub fn deploy_pool_kamino(ctx: Context<TransferPoolKamino>, amount: u64, min_out: u64) -> Result<()> {
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        ctx.accounts.config.require_not_paused(PAUSE_DEPLOYS)?;
+        ctx.accounts.registry.require_kamino(&ctx.accounts.usdc_mint.key(), &ctx.accounts.kamino_reserve.key())?;
+        let collateral_before = ctx.accounts.pool_collateral_vault.amount;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.vault_state.seeds()];
+        ctx.accounts.kamino_cpi().deposit(signer, amount)?;
+
+        let vault_state = &mut ctx.accounts.vault_state;
+        vault_state.kamino_deployed = vault_state.kamino_deployed.checked_add(amount).ok_or(YieldVaultErrors::MathOverflow)?;
+        ctx.accounts.pool_collateral_vault.reload()?;
+        let collateral_received = ctx.accounts.pool_collateral_vault.amount.saturating_sub(collateral_before);
+        let rate = KaminoExchangeRate::load(&ctx.accounts.kamino_reserve.to_account_info())?;
+        require!(rate.liquidity_for_collateral(collateral_received)? >= min_out, YieldVaultErrors::SlippageExceeded);
+        msg!("Deployed {} pool USDC to KLend, holding {} kUSDC", amount, ctx.accounts.pool_collateral_vault.amount);
+        emit!(DeployedToKamino {
+            vault: ctx.accounts.vault_state.key(),
+            amount,
+            collateral_received,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Fails with SlippageExceeded unless at least `min_out` USDC reaches the pool ATA.
+    pub fn redeem_pool_kamino(ctx: Context<TransferPoolKamino>, min_out: u64) -> Result<()> {
+        let collateral_amount = ctx.accounts.pool_collateral_vault.amount;
+        require!(collateral_amount > 0, YieldVaultErrors::NothingRedeemed);
+        let before = ctx.accounts.pool_usdc_ata.amount;
+
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.vault_state.seeds()];
+        ctx.accounts.kamino_cpi().redeem(signer, collateral_amount)?;
+
+        ctx.accounts.pool_usdc_ata.reload()?;
+        let received = ctx.accounts.pool_usdc_ata.amount.checked_sub(before).ok_or(YieldVaultErrors::MathOverflow)?;
+        require!(received >= min_out, YieldVaultErrors::SlippageExceeded);
+        ctx.accounts.vault_state.kamino_deployed = 0;
+        msg!("Redeemed {} kUSDC from KLend into {} pool USDC", collateral_amount, received);
+        emit!(RedeemedFromKamino {
+            vault: ctx.accounts.vault_state.key(),
+            collateral_amount,
+            amount_received: received,
+            fee: 0,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // `min_out` is the least USDC value, at the bank's share value, the Marginfi deposit must gain.
+    pub fn deploy_pool_marginfi(ctx: Context<TransferPoolMarginfi>, amount: u64, min_out: u64) -> Result<()> {
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        ctx.accounts.config.require_not_paused(PAUSE_DEPLOYS)?;
+        ctx.accounts.registry.require_marginfi(&ctx.accounts.usdc_mint.key(), &ctx.accounts.marginfi_bank.key())?;
+        let value_before = ctx.accounts.marginfi_value()?;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.vault_state.seeds()];
+        ctx.accounts.marginfi_cpi().deposit(signer, amount)?;
+        require!(ctx.accounts.marginfi_value()?.saturating_sub(value_before) >= min_out, YieldVaultErrors::SlippageExceeded);
+
+        let vault_state = &mut ctx.accounts.vault_state;
+        vault_state.marginfi_deployed = vault_state.marginfi_deployed.checked_add(amount).ok_or(YieldVaultErrors::MathOverflow)?;
+        msg!("Deployed {} pool USDC to Marginfi", amount);
+        emit!(DeployedToMarginfi {
+            vault: ctx.accounts.vault_state.key(),
+            amount,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Fails with SlippageExceeded unless at least `min_out` USDC reaches the pool ATA.
+    pub fn redeem_pool_marginfi(ctx: Context<TransferPoolMarginfi>, min_out: u64) -> Result<()> {
+        let before = ctx.accounts.pool_usdc_ata.amount;
+        let signer: &[&[&[u8]]] = &[&ctx.accounts.vault_state.seeds()];
+        let vault_authority = ctx.accounts.marginfi_bank_liquidity_vault_authority.to_account_info();
+        ctx.accounts.marginfi_cpi().withdraw(vault_authority, signer, 1, true)?;
+
+        ctx.accounts.pool_usdc_ata.reload()?;
+        let received = ctx.accounts.pool_usdc_ata.amount.checked_sub(before).ok_or(YieldVaultErrors::MathOverflow)?;
+        require!(received > 0, YieldVaultErrors::NothingRedeemed);
+        require!(received >= min_out, YieldVaultErrors::SlippageExceeded);
+        ctx.accounts.vault_state.marginfi_deployed = 0;
+        msg!("Withdrew {} pool USDC from Marginfi", received);
+        emit!(RedeemedFromMarginfi {
+            vault: ctx.accounts.vault_state.key(),
+            amount_received: received,
+            fee: 0,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    // Read-only valuation of the pool, simulated like `get_position`; the keeper derives the
+    // pool redeems' `min_out` from it.
+    pub fn get_pool_position(ctx: Context<GetPoolPosition>) -> Result<Position> {
+        let usdc_mint = ctx.accounts.usdc_mint.key();
+        check_kamino_reserve(&ctx.accounts.kamino_reserve.to_account_info(), &usdc_mint, &ctx.accounts.kamino_usdc_collateral_mint.key())?;
+        check_marginfi_bank(&ctx.accounts.marginfi_bank.to_account_info(), &usdc_mint)?;
+        let rate = KaminoExchangeRate::load(&ctx.accounts.kamino_reserve.to_account_info())?;
+        let idle = ctx.accounts.pool_usdc_ata.amount;
+        let kamino = rate.liquidity_for_collateral(ctx.accounts.pool_collateral_vault.amount)?;
+        let marginfi = marginfi_asset_value(&ctx.accounts.marginfi_account.to_account_info(), &ctx.accounts.marginfi_bank.to_account_info())?;
+        let total = idle
+            .checked_add(kamino)
+            .and_then(|v| v.checked_add(marginfi))
+            .ok_or(YieldVaultErrors::MathOverflow)?;
+        msg!("Pool {} holds {} USDC: idle {}, Kamino {}, Marginfi {}", ctx.accounts.vault_state.key(), total, idle, kamino, marginfi);
+        Ok(Position { idle, kamino, marginfi, total })
+    }
+
+    // Moves the idle USDC of a legacy per-user vault into the pool and mints shares for it,
+    // priced like `deposit_shares`. The keeper must unwind the user vault from Kamino/Marginfi first.
+    pub fn migrate_to_pool(ctx: Context<MigrateToPool>) -> Result<()> {
+        require!(ctx.accounts.user_vault_account.active_protocol == Protocol::None, YieldVaultErrors::FundsStillDeployed);
+        require!(ctx.accounts.kamino_usdc_collateral_vault.amount == 0, YieldVaultErrors::FundsStillDeployed);
+        let amount = ctx.accounts.user_usdc_vault_ata.amount;
+        require!(amount > 0, YieldVaultErrors::InvalidAmount);
+        let value = ctx.accounts.pool_value()?;
+        let now = Clock::get()?.unix_timestamp;
+        let fee = ctx.accounts.vault_state.settle_fees(&ctx.accounts.config, value.total, now)?;
+        let before = ctx.accounts.pool_usdc_ata.amount;
+
+        let user_vault_signer: &[&[&[u8]]] = &[&ctx.accounts.user_vault_account.seeds()];
+        let move_accounts = TransferChecked {
+            from: ctx.accounts.user_usdc_vault_ata.to_account_info(),
+            mint: ctx.accounts.usdc_mint.to_account_info(),
+            to: ctx.accounts.pool_usdc_ata.to_account_info(),
+            authority: ctx.accounts.user_vault_account.to_account_info(),
+        };
+        transfer_checked(CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), move_accounts, user_vault_signer), amount, ctx.accounts.usdc_mint.decimals)?;
+
+        ctx.accounts.pool_usdc_ata.reload()?;
+        let received = ctx.accounts.pool_usdc_ata.amount.checked_sub(before).ok_or(YieldVaultErrors::MathOverflow)?;
+        let shares = ctx.accounts.vault_state.shares_for_assets(received, ctx.accounts.share_mint.supply)?;
+        require!(shares > 0, YieldVaultErrors::InvalidAmount);
+
+        ctx.accounts.raise_idle(&value, fee)?;
+        let pool_signer: &[&[&[u8]]] = &[&ctx.accounts.vault_state.seeds()];
+        pay_fee(
+            &ctx.accounts.token_program, &ctx.accounts.usdc_mint, &ctx.accounts.pool_usdc_ata,
+            ctx.accounts.vault_state.to_account_info(), &ctx.accounts.treasury_usdc_ta, pool_signer, fee,
+        )?;
+        let mint_accounts = MintTo {
+            mint: ctx.accounts.share_mint.to_account_info(),
+            to: ctx.accounts.user_share_ta.to_account_info(),
+            authority: ctx.accounts.vault_state.to_account_info(),
+        };
+        mint_to(CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), mint_accounts, pool_signer), shares)?;
+
+        let vault_state = &mut ctx.accounts.vault_state;
+        vault_state.total_assets = vault_state.total_assets.checked_add(received).ok_or(YieldVaultErrors::MathOverflow)?;
+        ctx.accounts.user_vault_account.record_withdrawal(amount)?;
+        msg!("Migrated {} USDC of vault {} into pool for {} shares", received, ctx.accounts.user_vault_account.key(), shares);
         Ok(())
     }
 }
 
 
 
+// Every account struct that moves a vault's funds through Kamino/Marginfi names the market accounts
+// the same way (`kamino_*`, `marginfi_*`, `usdc_mint`, the token programs, `config`, `treasury_usdc_ta`);
+// only the vault side differs between user vaults and the pool. The CPI wrappers and fee settlement are
+// written once here and implemented per struct from the fields holding the signing vault PDA, its USDC
+// token account and its kUSDC account.
+macro_rules! impl_vault_accounts {
+    (kamino: $ty:ident { vault: $vault:ident, ata: $ata:ident, collateral: $collateral:ident }) => {
+        impl<'info> $ty<'info> {
+            pub fn kamino_cpi(&self) -> KaminoCpi<'info> {
+                KaminoCpi {
+                    program:                    self.kamino_program.to_account_info(),
+                    owner:                      self.$vault.to_account_info(),
+                    lending_market:             self.kamino_lending_market.to_account_info(),
+                    lending_market_authority:   self.kamino_lending_market_authority.to_account_info(),
+                    reserve:                    self.kamino_reserve.to_account_info(),
+                    reserve_liquidity_mint:     self.usdc_mint.to_account_info(),
+                    reserve_liquidity_supply:   self.kamino_reserve_liquidity_supply.to_account_info(),
+                    reserve_collateral_mint:    self.kamino_usdc_collateral_mint.to_account_info(),
+                    liquidity_account:          self.$ata.to_account_info(),
+                    collateral_account:         self.$collateral.to_account_info(),
+                    liquidity_token_program:    self.token_program.to_account_info(),
+                    collateral_token_program:   self.collateral_token_program.to_account_info(),
+                    instruction_sysvar_account: self.instruction_sysvar_account.to_account_info(),
+                }
+            }
+        }
+    };
+    (marginfi: $ty:ident { vault: $vault:ident, ata: $ata:ident }) => {
+        impl<'info> $ty<'info> {
+            pub fn marginfi_cpi(&self) -> MarginfiCpi<'info> {
+                MarginfiCpi {
+                    program:          self.marginfi_program.to_account_info(),
+                    group:            self.marginfi_group.to_account_info(),
+                    marginfi_account: self.marginfi_account.to_account_info(),
+                    authority:        self.$vault.to_account_info(),
+                    bank:             self.marginfi_bank.to_account_info(),
+                    liquidity_vault:  self.marginfi_bank_liquidity_vault.to_account_info(),
+                    token_account:    self.$ata.to_account_info(),
+                    mint:             self.usdc_mint.to_account_info(),
+                    token_program:    self.token_program.to_account_info(),
+                }
+            }
+
+            /// USDC value of the vault's deposit in `marginfi_bank` (see `marginfi_asset_value`).
+            pub fn marginfi_value(&self) -> Result<u64> {
+                marginfi_asset_value(&self.marginfi_account.to_account_info(), &self.marginfi_bank.to_account_info())
+            }
+        }
+    };
+    (fees: $ty:ident { vault: $vault:ident, ata: $ata:ident }, $($arg:ident: $arg_ty:ty),+) => {
+        impl<'info> $ty<'info> {
+            /// Settles the vault's management/performance fees (see the vault's own `settle_fees`)
+            /// and pays them to the treasury out of its USDC token account, which is reloaded.
+            pub fn settle_fees(&mut self, $($arg: $arg_ty),+) -> Result<u64> {
+                let now = Clock::get()?.unix_timestamp;
+                let fee = self.$vault.settle_fees(&self.config, $($arg,)+ now)?;
+                let signer: &[&[&[u8]]] = &[&self.$vault.seeds()];
+                pay_fee(&self.token_program, &self.usdc_mint, &self.$ata, self.$vault.to_account_info(), &self.treasury_usdc_ta, signer, fee)?;
+                self.$ata.reload()?;
+                Ok(fee)
+            }
+        }
+    };
+    (migrate: $ty:ident) => {
+        impl<'info> $ty<'info> {
+            /// Value of the vault being migrated: idle USDC, its kUSDC at the reserve's exchange rate and its
+            /// deposit in the registry's bank through `marginfi_account` as recorded in the vault (unset reads
+            /// as empty). As fresh as the last reserve refresh / bank accrual, like `get_position`.
+            pub fn vault_value(&self, marginfi_account: &Pubkey) -> Result<Position> {
+                let usdc_mint = self.usdc_mint.key();
+                let reserve = self.kamino_reserve.to_account_info();
+                self.registry.require_kamino(&usdc_mint, &reserve.key())?;
+                self.registry.require_marginfi(&usdc_mint, &self.marginfi_bank.key())?;
+                check_kamino_reserve(&reserve, &usdc_mint, &self.kamino_usdc_collateral_mint.key())?;
+                let kamino = KaminoExchangeRate::load(&reserve)?.liquidity_for_collateral(self.kamino_usdc_collateral_vault.amount)?;
+                let marginfi = if *marginfi_account == Pubkey::default() {
+                    0
+                } else {
+                    require_keys_eq!(self.marginfi_account.key(), *marginfi_account, YieldVaultErrors::InvalidMarginfiAccount);
+                    marginfi_asset_value(&self.marginfi_account.to_account_info(), &self.marginfi_bank.to_account_info())?
+                };
+                let idle = self.user_usdc_vault_ata.amount;
+                let total = idle
+                    .checked_add(kamino)
+                    .and_then(|v| v.checked_add(marginfi))
+                    .ok_or(YieldVaultErrors::MathOverflow)?;
+                Ok(Position { idle, kamino, marginfi, total })
+            }
+
+            /// Grows the baseline-layout vault `vault_info` to the current layout and fills in the new fields.
+            /// `holdings` picks `active_protocol` and `kamino_weight_bps` from the vault's value. Baseline vaults
+            /// keep their `[b"vault", owner]` address (`legacy_seeds`) and are bound to the mint of their existing
+            /// token ATA; their principal restarts at what they hold now (see `UserVault::reset_principal`).
+            pub fn upgrade_vault(
+                &self,
+                vault_info: &AccountInfo<'info>,
+                holdings: impl FnOnce(&Position) -> Result<(Protocol, u16)>,
+            ) -> Result<()> {
+                require!(vault_info.data_len() < UserVault::LEN, YieldVaultErrors::AlreadyMigrated);
+                grow_user_vault(&self.keeper, vault_info, &self.system_program)?;
+
+                let mut data = vault_info.try_borrow_mut_data()?;
+                let mut user_vault = UserVault::try_deserialize(&mut &data[..])?;
+                let value = self.vault_value(&user_vault.marginfi_account)?;
+                let (active_protocol, kamino_weight_bps) = holdings(&value)?;
+                user_vault.active_protocol = active_protocol;
+                user_vault.kamino_weight_bps = kamino_weight_bps;
+                user_vault.mint = self.usdc_mint.key();
+                user_vault.legacy_seeds = true;
+                user_vault.version = UserVault::VERSION;
+                user_vault.reset_principal(value.total, Clock::get()?.unix_timestamp);
+                user_vault.try_serialize(&mut &mut data[..])?;
+                msg!(
+                    "Migrated vault {} of owner {} to layout v{} with principal {}",
+                    vault_info.key(), user_vault.owner, UserVault::VERSION, user_vault.deposited_amount
+                );
+                Ok(())
+            }
+        }
+    };
+    (pool: $ty:ident) => {
+        impl<'info> $ty<'info> {
+            /// Live value of the pool: idle USDC, its kUSDC at the reserve's current exchange rate and its
+            /// Marginfi deposit, as `get_position` values a user vault. A pool holding kUSDC needs the reserve
+            /// refreshed earlier in the transaction. The reserve and bank must be the registry's, and the
+            /// collateral vault the pool's ATA of that reserve's kUSDC, or part of the pool would read as empty.
+            pub fn pool_value(&self) -> Result<Position> {
+                let usdc_mint = self.usdc_mint.key();
+                let reserve = self.kamino_reserve.to_account_info();
+                self.registry.require_kamino(&usdc_mint, &reserve.key())?;
+                self.registry.require_marginfi(&usdc_mint, &self.marginfi_bank.key())?;
+                check_kamino_reserve(&reserve, &usdc_mint, &self.kamino_usdc_collateral_mint.key())?;
+                let collateral = self.pool_collateral_vault.amount;
+                let kamino = if collateral > 0 {
+                    self.kamino_cpi().require_refreshed()?;
+                    KaminoExchangeRate::load(&reserve)?.liquidity_for_collateral(collateral)?
+                } else {
+                    0
+                };
+                let idle = self.pool_usdc_ata.amount;
+                let marginfi = marginfi_asset_value(&self.marginfi_account.to_account_info(), &self.marginfi_bank.to_account_info())?;
+                let total = idle
+                    .checked_add(kamino)
+                    .and_then(|v| v.checked_add(marginfi))
+                    .ok_or(YieldVaultErrors::MathOverflow)?;
+                Ok(Position { idle, kamino, marginfi, total })
+            }
+
+            /// Redeems whatever the pool ATA is short of `needed` USDC from Kamino and Marginfi, in proportion
+            /// to the pool's holdings in `value`. Deployed principal shrinks with the share of each position redeemed.
+            pub fn raise_idle(&mut self, value: &Position, needed: u64) -> Result<()> {
+                let shortfall = needed.saturating_sub(self.pool_usdc_ata.amount);
+                if shortfall == 0 {
+                    return Ok(());
+                }
+                let deployed = value.kamino.checked_add(value.marginfi).ok_or(YieldVaultErrors::MathOverflow)?;
+                require!(shortfall <= deployed, YieldVaultErrors::InsufficientFunds);
+                let (kamino_part, marginfi_part) = split_shortfall(shortfall, value.kamino, value.marginfi, mul_div(value.kamino, BPS_DENOMINATOR, deployed)?)?;
+
+                let collateral_held = self.pool_collateral_vault.amount;
+                let marginfi_withdraw_all = marginfi_part > 0 && marginfi_part >= value.marginfi;
+                let signer: &[&[&[u8]]] = &[&self.vault_state.seeds()];
+                if kamino_part > 0 {
+                    let collateral_amount = if kamino_part >= value.kamino {
+                        collateral_held
+                    } else {
+                        KaminoExchangeRate::load(&self.kamino_reserve.to_account_info())?
+                            .collateral_for_liquidity(kamino_part)?
+                            .min(collateral_held)
+                    };
+                    let before = self.pool_usdc_ata.amount;
+                    self.kamino_cpi().redeem(signer, collateral_amount)?;
+                    self.pool_collateral_vault.reload()?;
+                    self.pool_usdc_ata.reload()?;
+                    emit!(RedeemedFromKamino {
+                        vault: self.vault_state.key(),
+                        collateral_amount,
+                        amount_received: self.pool_usdc_ata.amount.saturating_sub(before),
+                        fee: 0,
+                        slot: Clock::get()?.slot,
+                    });
+                }
+                if marginfi_part > 0 {
+                    let before = self.pool_usdc_ata.amount;
+                    let vault_authority = self.marginfi_bank_liquidity_vault_authority.to_account_info();
+                    self.marginfi_cpi().withdraw(vault_authority, signer, marginfi_part, marginfi_withdraw_all)?;
+                    self.pool_usdc_ata.reload()?;
+                    emit!(RedeemedFromMarginfi {
+                        vault: self.vault_state.key(),
+                        amount_received: self.pool_usdc_ata.amount.saturating_sub(before),
+                        fee: 0,
+                        slot: Clock::get()?.slot,
+                    });
+                }
+
+                let vault_state = &mut self.vault_state;
+                if kamino_part > 0 {
+                    vault_state.kamino_deployed = mul_div(vault_state.kamino_deployed, self.pool_collateral_vault.amount, collateral_held)?;
+                }
+                if marginfi_withdraw_all {
+                    vault_state.marginfi_deployed = 0;
+                } else if marginfi_part > 0 {
+                    vault_state.marginfi_deployed = mul_div(vault_state.marginfi_deployed, value.marginfi - marginfi_part, value.marginfi)?;
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_vault_accounts!(kamino: TransferProtocolAssets { vault: user_vault_account, ata: user_usdc_vault_ata, collateral: kamino_usdc_collateral_vault });
+impl_vault_accounts!(marginfi: TransferProtocolAssets { vault: user_vault_account, ata: user_usdc_vault_ata });
+impl_vault_accounts!(kamino: TransferUsdcKamino { vault: user_vault_account, ata: user_usdc_vault_ata, collateral: kamino_usdc_collateral_vault });
+impl_vault_accounts!(fees: TransferUsdcKamino { vault: user_vault_account, ata: user_usdc_vault_ata }, value: u64);
+impl_vault_accounts!(marginfi: DepositUsdcMarginfi { vault: user_vault_account, ata: user_usdc_vault_ata });
+impl_vault_accounts!(marginfi: WithdrawUsdcMarginfi { vault: user_vault_account, ata: user_usdc_vault_ata });
+impl_vault_accounts!(fees: WithdrawUsdcMarginfi { vault: user_vault_account, ata: user_usdc_vault_ata }, value: u64);
+impl_vault_accounts!(kamino: Rebalance { vault: user_vault_account, ata: user_usdc_vault_ata, collateral: kamino_usdc_collateral_vault });
+impl_vault_accounts!(marginfi: Rebalance { vault: user_vault_account, ata: user_usdc_vault_ata });
+impl_vault_accounts!(fees: Rebalance { vault: user_vault_account, ata: user_usdc_vault_ata }, value: u64);
+impl_vault_accounts!(kamino: EmergencyRedeemKamino { vault: user_vault_account, ata: user_usdc_vault_ata, collateral: kamino_usdc_collateral_vault });
+impl_vault_accounts!(fees: EmergencyRedeemKamino { vault: user_vault_account, ata: user_usdc_vault_ata }, value: u64);
+impl_vault_accounts!(marginfi: EmergencyRedeemMarginfi { vault: user_vault_account, ata: user_usdc_vault_ata });
+impl_vault_accounts!(fees: EmergencyRedeemMarginfi { vault: user_vault_account, ata: user_usdc_vault_ata }, value: u64);
+impl_vault_accounts!(kamino: TransferPoolKamino { vault: vault_state, ata: pool_usdc_ata, collateral: pool_collateral_vault });
+impl_vault_accounts!(marginfi: TransferPoolMarginfi { vault: vault_state, ata: pool_usdc_ata });
+impl_vault_accounts!(kamino: TransferShares { vault: vault_state, ata: pool_usdc_ata, collateral: pool_collateral_vault });
+impl_vault_accounts!(marginfi: TransferShares { vault: vault_state, ata: pool_usdc_ata });
+impl_vault_accounts!(pool: TransferShares);
+impl_vault_accounts!(kamino: MigrateToPool { vault: vault_state, ata: pool_usdc_ata, collateral: pool_collateral_vault });
+impl_vault_accounts!(marginfi: MigrateToPool { vault: vault_state, ata: pool_usdc_ata });
+impl_vault_accounts!(pool: MigrateToPool);
+impl_vault_accounts!(migrate: MigrateUserVault);
+impl_vault_accounts!(migrate: MigrateVault);
+
 #[derive(Accounts)]
 pub struct TransferAssets<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    pub usdc_mint: Account<'info, Mint>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
     #[account(
         mut,
-        seeds = [VAULT_SEED, user.key().as_ref()],
-        bump = user_vault_account.bump
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
     )]
     pub user_vault_account: Account<'info, UserVault>,
 
@@ -244,87 +1502,227 @@ pub struct TransferAssets<'info> {
         mut, 
         constraint = user_usdc_ta.mint == usdc_mint.key(), 
         constraint = user_usdc_ta.owner == user.key())]
-    pub user_usdc_ta: Account<'info, TokenAccount>,
+    pub user_usdc_ta: InterfaceAccount<'info, TokenAccount>,
 
 
     #[account(
         mut,
         associated_token::mint = usdc_mint,
         associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
     )]
-    pub user_usdc_vault_ata: Account<'info, TokenAccount>,
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
 
-    pub token_program: Program<'info, Token>, 
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 
 }
-    
+
+// User-signed moves between the owner's token account and the protocols, through the vault
+// (`deposit_and_deploy` / `withdraw_from_protocol`).
+// Carries both protocols' accounts like `Rebalance`; only the vault's active ones are used.
 #[derive(Accounts)]
-pub struct Initialize<'info>{
+pub struct TransferProtocolAssets<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    pub usdc_mint: Account<'info, Mint>,
-
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
     #[account(
-        init,
-        payer = user,
-        space = UserVault::LEN,
-        seeds = [VAULT_SEED, user.key().as_ref()],
-        bump,
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
     )]
     pub user_vault_account: Account<'info, UserVault>,
 
     #[account(
-        init,
-        payer = user,
+        mut,
+        constraint = user_usdc_ta.mint == usdc_mint.key(),
+        constraint = user_usdc_ta.owner == user.key())]
+    pub user_usdc_ta: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
         associated_token::mint = usdc_mint,
         associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
     )]
-    pub user_usdc_vault: Account<'info, TokenAccount>,
-
-    // Kamino Specific Accounts:
-    pub kamino_usdc_collateral_mint: Account<'info, Mint>,
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // -------- Kamino (Lend) specific: BEGIN --------
+    pub kamino_program: Program<'info, KaminoLending>,
+    /// CHECK: Kamino's lending market account; must own the reserve (checked in KaminoCpi)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_lending_market: UncheckedAccount<'info>,
+    /// CHECK: Kamino's lending market authority PDA ([b"lma", market], checked in KaminoCpi)
+    pub kamino_lending_market_authority: UncheckedAccount<'info>,
+    /// CHECK: Kamino's reserve account for USDC; registry, market and mints checked in the instructions / KaminoCpi
+    #[account(mut, owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    /// CHECK: USDC Supply Token Account for Kamino Reserve; must be the reserve's supply vault (checked in KaminoCpi)
+    #[account(mut)]
+    pub kamino_reserve_liquidity_supply: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
     #[account(
-        init,
-        payer = user,
+        mut,
         associated_token::mint = kamino_usdc_collateral_mint,
         associated_token::authority = user_vault_account,
+        associated_token::token_program = collateral_token_program,
     )]
-    pub kamino_usdc_collateral_vault: Account<'info, TokenAccount>,
-
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    // -------- Kamino (Lend) specific: END --------
 
-    // Marginfi Specific Accounts:
-    // pub marginfi_program: Program<'info, Marginfi>,
-    /// CHECK: Marginfi group account
-   /// CHECK: marginfi group (owner checked)
+    // ---- Marginfi specific ----
+    /// CHECK: group owned by Marginfi
     #[account(owner = Marginfi::id())]
     pub marginfi_group: UncheckedAccount<'info>,
+    /// CHECK: the vault-owned marginfi account
+    #[account(mut, address = user_vault_account.marginfi_account)]
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: the registry's USDC bank, checked in the instructions (must be mutable; state updates)
+    #[account(mut, owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault authority PDA; [b"liquidity_vault_auth", bank], checked in MarginfiCpi
+    pub marginfi_bank_liquidity_vault_authority: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault; must match the bank (checked in MarginfiCpi)
     #[account(mut)]
-    pub marginfi_account: Signer<'info>,
+    pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
     pub marginfi_program: Program<'info, Marginfi>,
 
-
     // BUILT-IN ACCOUNTS:
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+
+    /// CHECK: Instruction Sysvar Account, also read to find the preceding klend `refresh_reserve`
+    #[account(address = sysvar_instructions::ID)]
+    pub instruction_sysvar_account: UncheckedAccount<'info>,
+}
+
+impl<'info> TransferProtocolAssets<'info> {
+    /// Transfers `amount` from the owner into the vault ATA and credits what arrived as principal
+    /// (Token-2022 transfer fees are withheld in the destination), enforcing the deposit caps.
+    pub fn receive_deposit(&mut self, amount: u64) -> Result<u64> {
+        let before = self.user_usdc_vault_ata.amount;
+        let vault_deposit_accounts = TransferChecked {
+            from: self.user_usdc_ta.to_account_info(),
+            mint: self.usdc_mint.to_account_info(),
+            to: self.user_usdc_vault_ata.to_account_info(),
+            authority: self.user.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(self.token_program.to_account_info(), vault_deposit_accounts);
+        transfer_checked(cpi_context, amount, self.usdc_mint.decimals)?;
+
+        self.user_usdc_vault_ata.reload()?;
+        let received = self.user_usdc_vault_ata.amount.checked_sub(before).ok_or(YieldVaultErrors::MathOverflow)?;
+        require!(received > 0, YieldVaultErrors::InvalidAmount);
+        let vault_total = self.user_vault_account.deposited_amount.checked_add(received).ok_or(YieldVaultErrors::MathOverflow)?;
+        self.config.record_deposit(Some(vault_total), received)?;
+        self.user_vault_account.deposited_amount = vault_total;
+        emit!(Deposited {
+            vault: self.user_vault_account.key(),
+            owner: self.user.key(),
+            amount: received,
+            principal: vault_total,
+            protocol: self.user_vault_account.active_protocol,
+            slot: Clock::get()?.slot,
+        });
+        Ok(received)
+    }
+
+}
+    
+#[derive(Accounts)]
+pub struct Initialize<'info>{
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = user,
+        space = UserVault::LEN,
+        seeds = [VAULT_SEED, user.key().as_ref(), usdc_mint.key().as_ref()],
+        bump,
+    )]
+    pub user_vault_account: Account<'info, UserVault>,
+
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Kamino Specific Accounts:
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+
+    // Marginfi Specific Accounts:
+    // pub marginfi_program: Program<'info, Marginfi>,
+    /// CHECK: Marginfi group account
+   /// CHECK: marginfi group (owner checked)
+    #[account(owner = Marginfi::id())]
+    pub marginfi_group: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub marginfi_account: Signer<'info>,
+    pub marginfi_program: Program<'info, Marginfi>,
+
+
+    // BUILT-IN ACCOUNTS:
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>, // kUSDC is always an SPL Token mint
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
 pub struct TransferUsdcKamino<'info> {
-    #[account(mut, constraint = keeper.key() == keeper_pubkey())]
+    #[account(mut)]
     pub keeper: Signer<'info>,
-    pub usdc_mint: Account<'info, Mint>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
 
     /// CHECK: User account
     pub user: UncheckedAccount<'info>,
 
     #[account(
         mut,
-        seeds = [VAULT_SEED, user.key().as_ref()],
-        bump = user_vault_account.bump
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
     )]
     pub user_vault_account: Account<'info, UserVault>,
     
@@ -332,66 +1730,78 @@ pub struct TransferUsdcKamino<'info> {
         mut,
         associated_token::mint = usdc_mint,
         associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
     )]
-    pub user_usdc_vault_ata: Account<'info, TokenAccount>,
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
 
      // -------- Kamino (Lend) specific: BEGIN --------
      /// MNT: KLend2g3cP87fffoy8q1mQqGKjrxjC8boSyAYavgmjD
      pub kamino_program: Program<'info, KaminoLending>,
-     /// CHECK: Kamino's lending market account
+     /// CHECK: Kamino's lending market account; must own the reserve (checked in KaminoCpi)
      /// MNT: 7u3HeHxYDLhnCoErrtycNokbQYbWGzLs6JSDqGAv5PfF
+     #[account(owner = KaminoLending::id())]
      pub kamino_lending_market: UncheckedAccount<'info>,
-     /// CHECK: Kamino's lending market authority PDA
-     /// MNT: ??????
+     /// CHECK: Kamino's lending market authority PDA ([b"lma", market], checked in KaminoCpi)
      pub kamino_lending_market_authority: UncheckedAccount<'info>,
  
-     /// CHECK: Kamino's reserve account for USDC
+     /// CHECK: Kamino's reserve account for USDC; market and mints checked in KaminoCpi
      /// MNT: D6q6wuQSrifJKZYpR1M8R4YawnLDtDsMmWM1NbBmgJ59
-     #[account(mut)]
+     #[account(mut, owner = KaminoLending::id())]
      pub kamino_reserve: UncheckedAccount<'info>,
  
-     /// CHECK: USDC Supply Token Account for Kamino Reserve
+     /// CHECK: USDC Supply Token Account for Kamino Reserve; must be the reserve's supply vault (checked in KaminoCpi)
      #[account(mut)]
      pub kamino_reserve_liquidity_supply: UncheckedAccount<'info>,
  
      /// MNT: B8V6WVjPxW1UGwVDfxH2d2r8SyT4cqn7dQRK6XneVa7D
      #[account(mut)]
-     pub kamino_usdc_collateral_mint: Account<'info, Mint>,
+     pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
  
      #[account(
          mut,
          associated_token::mint = kamino_usdc_collateral_mint,
          associated_token::authority = user_vault_account,
+         associated_token::token_program = collateral_token_program,
      )]
-     pub kamino_usdc_collateral_vault: Account<'info, TokenAccount>,
+     pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
      // -------- Kamino (Lend) specific: END --------
 
     // BUILT-IN ACCOUNTS:
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
     pub rent: Sysvar<'info, Rent>,
 
-    /// CHECK: Instruction Sysvar Account
+    /// CHECK: Instruction Sysvar Account, also read to find the preceding klend `refresh_reserve`
     #[account(address = sysvar_instructions::ID)]
     pub instruction_sysvar_account: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
 pub struct DepositUsdcMarginfi<'info> {
-    #[account(mut, constraint = keeper.key() == keeper_pubkey())]
-    pub keeper: Signer<'info>, 
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
 
     /// CHECK: User account
     pub user: UncheckedAccount<'info>,
 
-    pub usdc_mint: Account<'info, Mint>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
 
     // vault state PDA (authority for CPIs)
     #[account(
         mut,
-        seeds = [VAULT_SEED, user.key().as_ref()],
-        bump = user_vault_account.bump
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
     )]
     pub user_vault_account: Account<'info, UserVault>,
 
@@ -400,8 +1810,9 @@ pub struct DepositUsdcMarginfi<'info> {
         mut,
         associated_token::mint = usdc_mint,
         associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
     )]
-    pub user_usdc_vault_ata: Account<'info, TokenAccount>,
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
 
     // ---- Marginfi specific ----
     /// CHECK: owner-checked for safety
@@ -418,31 +1829,43 @@ pub struct DepositUsdcMarginfi<'info> {
     #[account(mut, owner = Marginfi::id())]
     pub marginfi_bank: UncheckedAccount<'info>,
 
-    /// CHECK: bank's liquidity vault (destination)
+    /// CHECK: bank's liquidity vault (destination); must match the bank (checked in MarginfiCpi)
     #[account(mut)]
     pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
     pub marginfi_program: Program<'info, Marginfi>,
 
     // SPL programs
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 #[derive(Accounts)]
 pub struct WithdrawUsdcMarginfi<'info> {
-    #[account(mut, constraint = keeper.key() == keeper_pubkey())]
-    pub keeper: Signer<'info>, 
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
     /// CHECK: User account
     pub user: UncheckedAccount<'info>,
 
-    pub usdc_mint: Account<'info, Mint>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
 
     // Vault PDA (authority) that “signs” CPIs via seeds
     #[account(
         mut,
-        seeds = [VAULT_SEED, user.key().as_ref()],
-        bump = user_vault_account.bump
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
     )]
     pub user_vault_account: Account<'info, UserVault>,
     
@@ -450,8 +1873,9 @@ pub struct WithdrawUsdcMarginfi<'info> {
         mut,
         associated_token::mint = usdc_mint,
         associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
     )]
-    pub user_usdc_vault_ata: Account<'info, TokenAccount>,
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
 
     // ---- Marginfi specific ----
     /// CHECK: group owned by Marginfi
@@ -466,62 +1890,1783 @@ pub struct WithdrawUsdcMarginfi<'info> {
     #[account(mut, owner = Marginfi::id())]
     pub marginfi_bank: UncheckedAccount<'info>,
 
-    /// CHECK: bank’s liquidity vault authority PDA
+    /// CHECK: bank’s liquidity vault authority PDA; [b"liquidity_vault_auth", bank], checked in MarginfiCpi
     pub marginfi_bank_liquidity_vault_authority: UncheckedAccount<'info>,
 
-    /// CHECK: bank’s liquidity vault (source of USDC)
+    /// CHECK: bank’s liquidity vault (source of USDC); must match the bank (checked in MarginfiCpi)
     #[account(mut)]
     pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
 
     pub marginfi_program: Program<'info, Marginfi>,
 
     // SPL
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
-#[account]
-pub struct UserVault {
-    pub bump: u8,               // Bump for the vault
-    pub owner: Pubkey,          // Owner of the vault
-    // pub usdc_vault: Pubkey,     // Token Account for USDC
-    pub marginfi_account: Pubkey, // Marginfi account
-    pub deposited_amount: u64,   // Amount of USDC deposited to the vault
+#[derive(Accounts)]
+pub struct Rebalance<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: User account
+    pub user: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
+    )]
+    pub user_vault_account: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // -------- Kamino (Lend) specific: BEGIN --------
+    pub kamino_program: Program<'info, KaminoLending>,
+    /// CHECK: Kamino's lending market account; must own the reserve (checked in KaminoCpi)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_lending_market: UncheckedAccount<'info>,
+    /// CHECK: Kamino's lending market authority PDA ([b"lma", market], checked in KaminoCpi)
+    pub kamino_lending_market_authority: UncheckedAccount<'info>,
+    /// CHECK: Kamino's reserve account for USDC; market and mints checked in KaminoCpi
+    #[account(mut, owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    /// CHECK: USDC Supply Token Account for Kamino Reserve; must be the reserve's supply vault (checked in KaminoCpi)
+    #[account(mut)]
+    pub kamino_reserve_liquidity_supply: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    // -------- Kamino (Lend) specific: END --------
+
+    // ---- Marginfi specific ----
+    /// CHECK: group owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_group: UncheckedAccount<'info>,
+    /// CHECK: the vault-owned marginfi account
+    #[account(mut, address = user_vault_account.marginfi_account)]
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: USDC bank (must be mutable; state updates)
+    #[account(mut, owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault authority PDA; [b"liquidity_vault_auth", bank], checked in MarginfiCpi
+    pub marginfi_bank_liquidity_vault_authority: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault; must match the bank (checked in MarginfiCpi)
+    #[account(mut)]
+    pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
+    pub marginfi_program: Program<'info, Marginfi>,
+
+    // BUILT-IN ACCOUNTS:
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+
+    /// CHECK: Instruction Sysvar Account, also read to find the preceding klend `refresh_reserve`
+    #[account(address = sysvar_instructions::ID)]
+    pub instruction_sysvar_account: UncheckedAccount<'info>,
 }
 
-impl UserVault {
-    pub const LEN: usize = 
-    8 + // discriminator
-    1 + // bump
-    32 + // owner
-    32 + // marginfi_account
-    8; // deposited_amount
+#[derive(Accounts)]
+pub struct MigrateUserVault<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
 
-    /// Returns the PDA seeds used to sign as this vault's PDA.
-    pub fn seeds<'a>(&'a self) -> [&'a [u8]; 3] {
-        [VAULT_SEED, self.owner.as_ref(), core::slice::from_ref(&self.bump)]
-    }
+    /// CHECK: User account
+    pub user: UncheckedAccount<'info>,
+
+    /// CHECK: legacy-layout UserVault; cannot be deserialized until it is grown.
+    /// Owner and seeds are checked here, the discriminator on deserialize.
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [VAULT_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub user_vault_account: UncheckedAccount<'info>,
+
+    // Mint the legacy vault was created for; its vault ATA must exist
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // Valuation accounts, read to reset the vault's principal
+    /// CHECK: Kamino reserve of the mint (registry-checked, discriminator checked on load)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = user_vault_account,
+    )]
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: the vault's marginfi account as recorded in the vault, checked in `vault_value`
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: bank of the mint (registry-checked), owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
 }
 
-pub const VAULT_SEED: &[u8] = b"vault";
-// pub const USDC_VAULT_TOKEN_ACCOUNT_SEED: &[u8] = b"usdc_vault";
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Copy)]
-pub enum Protocol {
-    None,
-    Kamino,
-    Marginfi,
+    /// CHECK: UserVault with the baseline layout; cannot be deserialized until it is grown.
+    /// Owner is checked here, the discriminator on deserialize.
+    #[account(mut, owner = crate::ID)]
+    pub user_vault_account: UncheckedAccount<'info>,
+
+    // Mint the baseline vault was created for; its vault ATA must exist
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // Valuation accounts, read to set the vault's protocol and principal
+    /// CHECK: Kamino reserve of the mint (registry-checked, discriminator checked on load)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: the vault's marginfi account as recorded in the vault, checked in `vault_value`
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: bank of the mint (registry-checked), owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
 }
 
-#[error_code]
-pub enum YieldVaultErrors {
-    #[msg("No liquidity was redeemed")]
-    NothingRedeemed,
-    #[msg("Amount must be greater than 0")]
-    InvalidAmount,
-    #[msg("Unauthorized action")]
-    Unauthorized,
-    #[msg("Funds are already deployed to a protocol")]
-    ProtocolAlreadyActive,
-    #[msg("Funds are not in the specified protocol")]
-    IncorrectProtocol,
+// -------- User escape hatch accounts --------
+// Same account sets as the keeper redeems, but signed by the vault owner.
+
+#[derive(Accounts)]
+pub struct EmergencyRedeemKamino<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint,
+        constraint = user_vault_account.owner == user.key() @ YieldVaultErrors::Unauthorized,
+    )]
+    pub user_vault_account: Account<'info, UserVault>,
+    
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+     // -------- Kamino (Lend) specific: BEGIN --------
+     /// MNT: KLend2g3cP87fffoy8q1mQqGKjrxjC8boSyAYavgmjD
+     pub kamino_program: Program<'info, KaminoLending>,
+     /// CHECK: Kamino's lending market account; must own the reserve (checked in KaminoCpi)
+     /// MNT: 7u3HeHxYDLhnCoErrtycNokbQYbWGzLs6JSDqGAv5PfF
+     #[account(owner = KaminoLending::id())]
+     pub kamino_lending_market: UncheckedAccount<'info>,
+     /// CHECK: Kamino's lending market authority PDA ([b"lma", market], checked in KaminoCpi)
+     pub kamino_lending_market_authority: UncheckedAccount<'info>,
+ 
+     /// CHECK: Kamino's reserve account for USDC; market and mints checked in KaminoCpi
+     /// MNT: D6q6wuQSrifJKZYpR1M8R4YawnLDtDsMmWM1NbBmgJ59
+     #[account(mut, owner = KaminoLending::id())]
+     pub kamino_reserve: UncheckedAccount<'info>,
+ 
+     /// CHECK: USDC Supply Token Account for Kamino Reserve; must be the reserve's supply vault (checked in KaminoCpi)
+     #[account(mut)]
+     pub kamino_reserve_liquidity_supply: UncheckedAccount<'info>,
+ 
+     /// MNT: B8V6WVjPxW1UGwVDfxH2d2r8SyT4cqn7dQRK6XneVa7D
+     #[account(mut)]
+     pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+ 
+     #[account(
+         mut,
+         associated_token::mint = kamino_usdc_collateral_mint,
+         associated_token::authority = user_vault_account,
+         associated_token::token_program = collateral_token_program,
+     )]
+     pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+     // -------- Kamino (Lend) specific: END --------
+
+    // BUILT-IN ACCOUNTS:
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+
+    /// CHECK: Instruction Sysvar Account, also read to find the preceding klend `refresh_reserve`
+    #[account(address = sysvar_instructions::ID)]
+    pub instruction_sysvar_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EmergencyRedeemMarginfi<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Vault PDA (authority) that “signs” CPIs via seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint,
+        constraint = user_vault_account.owner == user.key() @ YieldVaultErrors::Unauthorized,
+    )]
+    pub user_vault_account: Account<'info, UserVault>,
+    
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // ---- Marginfi specific ----
+    /// CHECK: group owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_group: UncheckedAccount<'info>,
+
+    /// CHECK: the vault-owned marginfi account
+    #[account(mut, address = user_vault_account.marginfi_account)]
+    pub marginfi_account: UncheckedAccount<'info>,
+
+    /// CHECK: USDC bank (must be mutable; state updates)
+    #[account(mut, owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+
+    /// CHECK: bank’s liquidity vault authority PDA; [b"liquidity_vault_auth", bank], checked in MarginfiCpi
+    pub marginfi_bank_liquidity_vault_authority: UncheckedAccount<'info>,
+
+    /// CHECK: bank’s liquidity vault (source of USDC); must match the bank (checked in MarginfiCpi)
+    #[account(mut)]
+    pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
+
+    pub marginfi_program: Program<'info, Marginfi>,
+
+    // SPL
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct KeeperHeartbeat<'info> {
+    pub keeper: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVault<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+
+    // Closed last by Anchor, after the CPIs below have used it as signer
+    #[account(
+        mut,
+        close = user,
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint,
+        constraint = user_vault_account.owner == user.key() @ YieldVaultErrors::Unauthorized,
+    )]
+    pub user_vault_account: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the vault-owned marginfi account
+    #[account(mut, address = user_vault_account.marginfi_account)]
+    pub marginfi_account: UncheckedAccount<'info>,
+    pub marginfi_program: Program<'info, Marginfi>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct GetPosition<'info> {
+    /// CHECK: vault owner, only used for the PDA seeds
+    pub user: UncheckedAccount<'info>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
+    )]
+    pub user_vault_account: Account<'info, UserVault>,
+    #[account(
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Kamino reserve of the mint (discriminator checked on load)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the vault-owned marginfi account
+    #[account(address = user_vault_account.marginfi_account, owner = Marginfi::id())]
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: bank of the mint, owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+}
+
+// -------- Program config accounts --------
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut, constraint = payer.key() == keeper_pubkey() @ YieldVaultErrors::Unauthorized)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = VaultConfig::LEN,
+        seeds = [CONFIG_SEED],
+        bump,
+    )]
+    pub config: Account<'info, VaultConfig>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteConfigChange<'info> {
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    // Only needed for SetMarkets / RemoveMarkets
+    #[account(mut, seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Option<Account<'info, ProtocolRegistry>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeRegistry<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = ProtocolRegistry::LEN,
+        seeds = [REGISTRY_SEED],
+        bump,
+    )]
+    pub registry: Account<'info, ProtocolRegistry>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPause<'info> {
+    pub authority: Signer<'info>, // guardian or admin
+
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = authority.key() == config.guardian || authority.key() == config.admin @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    pub pending_admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.pending_admin == Some(pending_admin.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+}
+
+// -------- Pooled vault accounts --------
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = keeper,
+        space = VaultState::LEN,
+        seeds = [VAULT_STATE_SEED, usdc_mint.key().as_ref()],
+        bump,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+
+    // vyUSDC: share mint owned by the pool PDA
+    #[account(
+        init,
+        payer = keeper,
+        seeds = [SHARE_MINT_SEED, vault_state.key().as_ref()],
+        bump,
+        mint::decimals = usdc_mint.decimals,
+        mint::authority = vault_state,
+    )]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = keeper,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_usdc_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // Kamino Specific Accounts:
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = keeper,
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub pool_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Marginfi Specific Accounts:
+    /// CHECK: marginfi group (owner checked)
+    #[account(owner = Marginfi::id())]
+    pub marginfi_group: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub marginfi_account: Signer<'info>,
+    pub marginfi_program: Program<'info, Marginfi>,
+
+    // BUILT-IN ACCOUNTS:
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct TransferShares<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_STATE_SEED, usdc_mint.key().as_ref()],
+        bump = vault_state.bump,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(mut, address = vault_state.share_mint)]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut, 
+        constraint = user_usdc_ta.mint == usdc_mint.key(), 
+        constraint = user_usdc_ta.owner == user.key())]
+    pub user_usdc_ta: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = share_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_share_ta: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_usdc_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
+    #[account(
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // -------- Kamino (Lend) specific: BEGIN --------
+    pub kamino_program: Program<'info, KaminoLending>,
+    /// CHECK: Kamino's lending market account; must own the reserve (checked in KaminoCpi)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_lending_market: UncheckedAccount<'info>,
+    /// CHECK: Kamino's lending market authority PDA ([b"lma", market], checked in KaminoCpi)
+    pub kamino_lending_market_authority: UncheckedAccount<'info>,
+    /// CHECK: Kamino's reserve account for USDC; market and mints checked in KaminoCpi / `pool_value`
+    #[account(mut, owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    /// CHECK: USDC Supply Token Account for Kamino Reserve; must be the reserve's supply vault (checked in KaminoCpi)
+    #[account(mut)]
+    pub kamino_reserve_liquidity_supply: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub pool_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    // -------- Kamino (Lend) specific: END --------
+
+    // ---- Marginfi specific ----
+    /// CHECK: group owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_group: UncheckedAccount<'info>,
+    /// CHECK: the pool-owned marginfi account
+    #[account(mut, address = vault_state.marginfi_account)]
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: the registry's USDC bank (checked in `pool_value`; must be mutable, state updates)
+    #[account(mut, owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault authority PDA; [b"liquidity_vault_auth", bank], checked in MarginfiCpi
+    pub marginfi_bank_liquidity_vault_authority: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault; must match the bank (checked in MarginfiCpi)
+    #[account(mut)]
+    pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
+    pub marginfi_program: Program<'info, Marginfi>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: Instruction Sysvar Account, also read to find the preceding klend `refresh_reserve`
+    #[account(address = sysvar_instructions::ID)]
+    pub instruction_sysvar_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TransferPoolKamino<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_STATE_SEED, usdc_mint.key().as_ref()],
+        bump = vault_state.bump,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_usdc_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // -------- Kamino (Lend) specific: BEGIN --------
+    pub kamino_program: Program<'info, KaminoLending>,
+    /// CHECK: Kamino's lending market account; must own the reserve (checked in KaminoCpi)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_lending_market: UncheckedAccount<'info>,
+    /// CHECK: Kamino's lending market authority PDA ([b"lma", market], checked in KaminoCpi)
+    pub kamino_lending_market_authority: UncheckedAccount<'info>,
+    /// CHECK: Kamino's reserve account for USDC; market and mints checked in KaminoCpi
+    #[account(mut, owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    /// CHECK: USDC Supply Token Account for Kamino Reserve; must be the reserve's supply vault (checked in KaminoCpi)
+    #[account(mut)]
+    pub kamino_reserve_liquidity_supply: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub pool_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    // -------- Kamino (Lend) specific: END --------
+
+    // BUILT-IN ACCOUNTS:
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+
+    /// CHECK: Instruction Sysvar Account, also read to find the preceding klend `refresh_reserve`
+    #[account(address = sysvar_instructions::ID)]
+    pub instruction_sysvar_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TransferPoolMarginfi<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump = config.bump,
+        constraint = config.is_keeper(&keeper.key()) @ YieldVaultErrors::Unauthorized,
+    )]
+    pub config: Account<'info, VaultConfig>,
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_STATE_SEED, usdc_mint.key().as_ref()],
+        bump = vault_state.bump,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_usdc_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // ---- Marginfi specific ----
+    /// CHECK: group owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_group: UncheckedAccount<'info>,
+
+    /// CHECK: the pool-owned marginfi account
+    #[account(mut, address = vault_state.marginfi_account)]
+    pub marginfi_account: UncheckedAccount<'info>,
+
+    /// CHECK: USDC bank (must be mutable; state updates)
+    #[account(mut, owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+
+    /// CHECK: bank’s liquidity vault authority PDA (only used on redeem); [b"liquidity_vault_auth", bank], checked in MarginfiCpi
+    pub marginfi_bank_liquidity_vault_authority: UncheckedAccount<'info>,
+
+    /// CHECK: bank’s liquidity vault; must match the bank (checked in MarginfiCpi)
+    #[account(mut)]
+    pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
+
+    pub marginfi_program: Program<'info, Marginfi>,
+
+    // SPL
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolPosition<'info> {
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds = [VAULT_STATE_SEED, usdc_mint.key().as_ref()], bump = vault_state.bump)]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        associated_token::mint = usdc_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_usdc_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Kamino reserve of the mint (discriminator checked on load)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub pool_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the pool-owned marginfi account
+    #[account(address = vault_state.marginfi_account, owner = Marginfi::id())]
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: bank of the mint, owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateToPool<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, VaultConfig>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, user.key().as_ref(), user_vault_account.mint_seed()],
+        bump = user_vault_account.bump,
+        constraint = user_vault_account.mint == usdc_mint.key() @ YieldVaultErrors::InvalidMint
+    )]
+    pub user_vault_account: Account<'info, UserVault>,
+
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = user_vault_account,
+        associated_token::token_program = token_program,
+    )]
+    pub user_usdc_vault_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = user_vault_account,
+    )]
+    pub kamino_usdc_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [VAULT_STATE_SEED, usdc_mint.key().as_ref()],
+        bump = vault_state.bump,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+
+    #[account(mut, address = vault_state.share_mint)]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = share_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+    )]
+    pub user_share_ta: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_usdc_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(seeds = [REGISTRY_SEED], bump = registry.bump)]
+    pub registry: Account<'info, ProtocolRegistry>,
+    #[account(
+        mut,
+        constraint = treasury_usdc_ta.owner == config.treasury && treasury_usdc_ta.mint == usdc_mint.key() @ YieldVaultErrors::InvalidTreasury,
+    )]
+    pub treasury_usdc_ta: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // -------- Kamino (Lend) specific: BEGIN --------
+    pub kamino_program: Program<'info, KaminoLending>,
+    /// CHECK: Kamino's lending market account; must own the reserve (checked in KaminoCpi)
+    #[account(owner = KaminoLending::id())]
+    pub kamino_lending_market: UncheckedAccount<'info>,
+    /// CHECK: Kamino's lending market authority PDA ([b"lma", market], checked in KaminoCpi)
+    pub kamino_lending_market_authority: UncheckedAccount<'info>,
+    /// CHECK: Kamino's reserve account for USDC; market and mints checked in KaminoCpi / `pool_value`
+    #[account(mut, owner = KaminoLending::id())]
+    pub kamino_reserve: UncheckedAccount<'info>,
+    /// CHECK: USDC Supply Token Account for Kamino Reserve; must be the reserve's supply vault (checked in KaminoCpi)
+    #[account(mut)]
+    pub kamino_reserve_liquidity_supply: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub kamino_usdc_collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = kamino_usdc_collateral_mint,
+        associated_token::authority = vault_state,
+        associated_token::token_program = collateral_token_program,
+    )]
+    pub pool_collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    // -------- Kamino (Lend) specific: END --------
+
+    // ---- Marginfi specific ----
+    /// CHECK: group owned by Marginfi
+    #[account(owner = Marginfi::id())]
+    pub marginfi_group: UncheckedAccount<'info>,
+    /// CHECK: the pool-owned marginfi account
+    #[account(mut, address = vault_state.marginfi_account)]
+    pub marginfi_account: UncheckedAccount<'info>,
+    /// CHECK: the registry's USDC bank (checked in `pool_value`; must be mutable, state updates)
+    #[account(mut, owner = Marginfi::id())]
+    pub marginfi_bank: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault authority PDA; [b"liquidity_vault_auth", bank], checked in MarginfiCpi
+    pub marginfi_bank_liquidity_vault_authority: UncheckedAccount<'info>,
+    /// CHECK: bank’s liquidity vault; must match the bank (checked in MarginfiCpi)
+    #[account(mut)]
+    pub marginfi_bank_liquidity_vault: UncheckedAccount<'info>,
+    pub marginfi_program: Program<'info, Marginfi>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub collateral_token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: Instruction Sysvar Account, also read to find the preceding klend `refresh_reserve`
+    #[account(address = sysvar_instructions::ID)]
+    pub instruction_sysvar_account: UncheckedAccount<'info>,
+}
+
+#[account]
+pub struct UserVault {
+    pub bump: u8,               // Bump for the vault
+    pub owner: Pubkey,          // Owner of the vault
+    // pub usdc_vault: Pubkey,     // Token Account for USDC
+    pub marginfi_account: Pubkey, // Marginfi account
+    pub deposited_amount: u64,   // Principal: USDC deposited minus principal withdrawn
+    pub active_protocol: Protocol, // Protocol currently holding the vault's deployed funds
+    pub fee_checkpoint: i64,     // Unix time management fees were last settled (0 = never)
+    pub realized_yield: u64,     // Realized (fee-assessed) yield still held by the vault
+    pub withdrawn_amount: u64,   // Cumulative USDC withdrawn by the owner
+    pub mint: Pubkey,            // Token held by the vault (part of the PDA seeds)
+    pub legacy_seeds: bool,      // Created before multi-mint: PDA is [b"vault", owner], without the mint
+    pub kamino_weight_bps: u16,  // Target share of the vault's funds in Kamino; Marginfi gets the rest
+    pub version: u8,             // Layout version (UserVault::VERSION); the baseline layout, without this field, is version 0
+    pub reserved: [u8; 64],      // Zeroed space for future fields, so adding them needs no realloc
+}
+
+impl UserVault {
+    pub const LEN: usize = 
+    8 + // discriminator
+    1 + // bump
+    32 + // owner
+    32 + // marginfi_account
+    8 + // deposited_amount
+    1 + // active_protocol
+    8 + // fee_checkpoint
+    8 + // realized_yield
+    8 + // withdrawn_amount
+    32 + // mint
+    1 + // legacy_seeds
+    2 + // kamino_weight_bps
+    1 + // version
+    64; // reserved
+
+    /// Current layout version, bumped whenever fields are carved out of `reserved` or appended.
+    pub const VERSION: u8 = 1;
+
+
+    /// Mint component of the PDA seeds. Empty for legacy USDC vaults: an empty seed adds
+    /// nothing to the derivation, so `[b"vault", owner, b""]` is the legacy address.
+    pub fn mint_seed(&self) -> &[u8] {
+        if self.legacy_seeds { &[] } else { self.mint.as_ref() }
+    }
+
+    /// Returns the PDA seeds used to sign as this vault's PDA.
+    pub fn seeds(&self) -> [&[u8]; 4] {
+        [VAULT_SEED, self.owner.as_ref(), self.mint_seed(), core::slice::from_ref(&self.bump)]
+    }
+
+    /// Fails unless some of the vault's funds are deployed to `protocol`.
+    pub fn require_protocol(&self, protocol: Protocol) -> Result<()> {
+        require!(self.active_protocol.includes(protocol), YieldVaultErrors::IncorrectProtocol);
+        Ok(())
+    }
+
+    /// Marks `protocol` as active; topping up an already active protocol is allowed.
+    /// Entering from idle restarts the management fee clock, so idle time is not charged.
+    pub fn enter_protocol(&mut self, protocol: Protocol) -> Result<()> {
+        require!(
+            self.active_protocol == Protocol::None || self.active_protocol.includes(protocol),
+            YieldVaultErrors::ProtocolAlreadyActive
+        );
+        if self.active_protocol == Protocol::None {
+            self.fee_checkpoint = Clock::get()?.unix_timestamp;
+            self.active_protocol = protocol;
+        }
+        Ok(())
+    }
+
+    /// Records that everything deployed to `protocol` was redeemed; a split vault keeps the other side.
+    pub fn exit_protocol(&mut self, protocol: Protocol) {
+        self.active_protocol = match (self.active_protocol, protocol) {
+            (Protocol::Split, Protocol::Kamino) => Protocol::Marginfi,
+            (Protocol::Split, Protocol::Marginfi) => Protocol::Kamino,
+            _ => Protocol::None,
+        };
+    }
+
+    /// Sets `active_protocol` from where the vault holds funds after an allocation.
+    pub fn set_holdings(&mut self, kamino: bool, marginfi: bool) -> Result<()> {
+        let protocol = match (kamino, marginfi) {
+            (true, true) => Protocol::Split,
+            (true, false) => Protocol::Kamino,
+            (false, true) => Protocol::Marginfi,
+            (false, false) => Protocol::None,
+        };
+        if self.active_protocol == Protocol::None && protocol != Protocol::None {
+            self.fee_checkpoint = Clock::get()?.unix_timestamp;
+        }
+        self.active_protocol = protocol;
+        Ok(())
+    }
+
+    /// Restarts fee accounting of a migrated vault at its current `value`. Older program versions never
+    /// took withdrawals out of `deposited_amount`, so the recorded principal can't serve as a high-water
+    /// mark: everything the vault holds becomes principal, yield earned before the migration is never
+    /// charged a performance fee and principal never counts as gain.
+    pub fn reset_principal(&mut self, value: u64, now: i64) {
+        self.deposited_amount = value;
+        self.realized_yield = 0;
+        self.fee_checkpoint = now;
+    }
+
+    /// Books a withdrawal of `amount`: realized yield is paid out first, then principal.
+    /// Anything beyond both is yield not yet realized through a redeem.
+    pub fn record_withdrawal(&mut self, amount: u64) -> Result<()> {
+        let from_yield = amount.min(self.realized_yield);
+        self.realized_yield -= from_yield;
+        let from_principal = (amount - from_yield).min(self.deposited_amount);
+        self.deposited_amount -= from_principal;
+        self.withdrawn_amount = self.withdrawn_amount.checked_add(amount).ok_or(YieldVaultErrors::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Fee owed on the vault being worth `value` (idle USDC plus positions). Yield is whatever sits
+    /// above principal and previously fee-assessed yield; `realized_yield` moves up to the post-fee value.
+    /// Settled on full redeems and before every payout that can include yield, so withdrawals never
+    /// take unassessed gains.
+    pub fn settle_fees(&mut self, config: &VaultConfig, value: u64, now: i64) -> Result<u64> {
+        let high_water = self.deposited_amount.checked_add(self.realized_yield).ok_or(YieldVaultErrors::MathOverflow)?;
+        let gain = value.saturating_sub(high_water);
+        let fee = config.fee_for(self.deposited_amount, gain, self.fee_checkpoint, now)?.min(value);
+        self.realized_yield = (value - fee).saturating_sub(self.deposited_amount);
+        self.fee_checkpoint = now;
+        Ok(fee)
+    }
+}
+
+#[account]
+pub struct VaultState {
+    pub bump: u8,                 // Bump for the pool PDA
+    pub usdc_mint: Pubkey,        // Underlying mint of the pool
+    pub share_mint: Pubkey,       // vyUSDC share mint (authority: this PDA)
+    pub marginfi_account: Pubkey, // Pool-owned Marginfi account
+    pub total_assets: u64,        // Pool value net of fees at the last settlement, plus deposits less withdrawals since
+    pub kamino_deployed: u64,     // USDC principal currently deployed to Kamino
+    pub marginfi_deployed: u64,   // USDC principal currently deployed to Marginfi
+    pub fee_checkpoint: i64,      // Unix time management fees were last settled (0 = never)
+}
+
+impl VaultState {
+    pub const LEN: usize =
+    8 + // discriminator
+    1 + // bump
+    32 + // usdc_mint
+    32 + // share_mint
+    32 + // marginfi_account
+    8 + // total_assets
+    8 + // kamino_deployed
+    8 + // marginfi_deployed
+    8; // fee_checkpoint
+
+    /// Returns the PDA seeds used to sign as the pool PDA.
+    pub fn seeds(&self) -> [&[u8]; 3] {
+        [VAULT_STATE_SEED, self.usdc_mint.as_ref(), core::slice::from_ref(&self.bump)]
+    }
+
+    /// Shares minted for `assets` USDC: assets * supply / total_assets (1:1 for an empty pool).
+    /// Only a fair price right after `settle_fees` brought `total_assets` to the pool's live value.
+    pub fn shares_for_assets(&self, assets: u64, share_supply: u64) -> Result<u64> {
+        if share_supply == 0 || self.total_assets == 0 {
+            return Ok(assets);
+        }
+        mul_div(assets, share_supply, self.total_assets)
+    }
+
+    /// USDC paid out for burning `shares`: shares * total_assets / supply.
+    pub fn assets_for_shares(&self, shares: u64, share_supply: u64) -> Result<u64> {
+        require!(share_supply > 0, YieldVaultErrors::InvalidAmount);
+        mul_div(shares, self.total_assets, share_supply)
+    }
+
+    /// Fee owed on the pool being worth `value` (see `pool_value`): management fee on it since the last
+    /// settlement plus the performance fee on its growth over `total_assets`, which moves to the post-fee
+    /// value so yield (or loss) is reflected in the share price.
+    pub fn settle_fees(&mut self, config: &VaultConfig, value: u64, now: i64) -> Result<u64> {
+        let gain = value.saturating_sub(self.total_assets);
+        let fee = config.fee_for(value, gain, self.fee_checkpoint, now)?.min(value);
+        self.total_assets = value - fee;
+        self.fee_checkpoint = now;
+        Ok(fee)
+    }
+}
+
+#[account]
+pub struct VaultConfig {
+    pub bump: u8,                      // Bump for the config PDA
+    pub admin: Pubkey,                 // Manages the keeper set
+    pub pending_admin: Option<Pubkey>, // Nominated admin, until accepted
+    pub keepers: Vec<Pubkey>,          // Authorized keepers
+    pub guardian: Pubkey,              // May set the pause flags
+    pub paused: u8,                    // Bitmask of PAUSE_* flags
+    pub treasury: Pubkey,              // Owner of the token accounts receiving fees
+    pub management_fee_bps: u16,       // Yearly fee on assets under management
+    pub performance_fee_bps: u16,      // Fee on realized yield
+    pub max_vault_deposit: u64,        // Per-vault deposit cap (0 = no cap)
+    pub max_total_deposits: u64,       // Program-wide deposit cap (0 = no cap)
+    pub total_deposits: u64,           // Net USDC deposited across all vaults and the pool
+    pub emergency_timeout: i64,        // Keeper inactivity (s) before emergency redeems open (0 = always open)
+    pub last_keeper_heartbeat: i64,    // Unix time of the last keeper_heartbeat
+    pub timelock_delay: i64,           // Seconds between proposing and executing a config change
+    pub next_change_id: u64,           // Id of the next proposed config change
+    pub pending_changes: Vec<PendingChange>, // Proposed config changes, until executed or cancelled
+}
+
+impl VaultConfig {
+    pub const MAX_KEEPERS: usize = 8;
+    pub const MAX_PENDING_CHANGES: usize = 8;
+    pub const LEN: usize =
+    8 + // discriminator
+    1 + // bump
+    32 + // admin
+    1 + 32 + // pending_admin
+    4 + 32 * Self::MAX_KEEPERS + // keepers
+    32 + // guardian
+    1 + // paused
+    32 + // treasury
+    2 + // management_fee_bps
+    2 + // performance_fee_bps
+    8 + // max_vault_deposit
+    8 + // max_total_deposits
+    8 + // total_deposits
+    8 + // emergency_timeout
+    8 + // last_keeper_heartbeat
+    8 + // timelock_delay
+    8 + // next_change_id
+    4 + PendingChange::LEN * Self::MAX_PENDING_CHANGES; // pending_changes
+
+    pub fn is_keeper(&self, key: &Pubkey) -> bool {
+        self.keepers.contains(key)
+    }
+
+    pub fn require_not_paused(&self, flag: u8) -> Result<()> {
+        require!(self.paused & flag == 0, YieldVaultErrors::Paused);
+        Ok(())
+    }
+
+    /// Counts `amount` of new deposits, failing if the program-wide cap or (for per-user vaults,
+    /// given the vault's total after the deposit) the per-vault cap would be exceeded.
+    pub fn record_deposit(&mut self, vault_total: Option<u64>, amount: u64) -> Result<()> {
+        if let Some(vault_total) = vault_total {
+            require!(self.max_vault_deposit == 0 || vault_total <= self.max_vault_deposit, YieldVaultErrors::DepositCapExceeded);
+        }
+        let total = self.total_deposits.checked_add(amount).ok_or(YieldVaultErrors::MathOverflow)?;
+        require!(self.max_total_deposits == 0 || total <= self.max_total_deposits, YieldVaultErrors::DepositCapExceeded);
+        self.total_deposits = total;
+        Ok(())
+    }
+
+    /// Fails while the keeper is considered alive (heartbeat within `emergency_timeout`).
+    pub fn require_keeper_inactive(&self, now: i64) -> Result<()> {
+        if self.emergency_timeout > 0 {
+            let idle_for = now.saturating_sub(self.last_keeper_heartbeat);
+            require!(idle_for >= self.emergency_timeout, YieldVaultErrors::KeeperStillActive);
+        }
+        Ok(())
+    }
+
+    pub fn pending_change_index(&self, id: u64) -> Result<usize> {
+        self.pending_changes.iter().position(|c| c.id == id).ok_or(YieldVaultErrors::ConfigChangeNotFound.into())
+    }
+
+    /// Applies an executed timelocked change.
+    pub fn apply(&mut self, change: ConfigChange) -> Result<()> {
+        change.validate()?;
+        match change {
+            ConfigChange::AddKeeper { keeper } => {
+                require!(!self.is_keeper(&keeper), YieldVaultErrors::KeeperAlreadyAdded);
+                require!(self.keepers.len() < Self::MAX_KEEPERS, YieldVaultErrors::TooManyKeepers);
+                self.keepers.push(keeper);
+                msg!("Keeper {} added", keeper);
+            }
+            ConfigChange::RemoveKeeper { keeper } => {
+                let index = self.keepers.iter().position(|k| *k == keeper).ok_or(YieldVaultErrors::KeeperNotFound)?;
+                self.keepers.swap_remove(index);
+                msg!("Keeper {} removed", keeper);
+            }
+            ConfigChange::SetGuardian { guardian } => {
+                self.guardian = guardian;
+                msg!("Guardian set to {}", guardian);
+            }
+            ConfigChange::SetFees { management_fee_bps, performance_fee_bps } => {
+                self.management_fee_bps = management_fee_bps;
+                self.performance_fee_bps = performance_fee_bps;
+                msg!("Fees set: management {} bps/year, performance {} bps", management_fee_bps, performance_fee_bps);
+            }
+            ConfigChange::SetTreasury { treasury } => {
+                self.treasury = treasury;
+                msg!("Treasury set to {}", treasury);
+            }
+            ConfigChange::SetDepositCaps { max_vault_deposit, max_total_deposits } => {
+                self.max_vault_deposit = max_vault_deposit;
+                self.max_total_deposits = max_total_deposits;
+                msg!("Deposit caps set: {} per vault, {} program-wide", max_vault_deposit, max_total_deposits);
+            }
+            ConfigChange::SetEmergencyTimeout { emergency_timeout } => {
+                self.emergency_timeout = emergency_timeout;
+                msg!("Emergency timeout set to {}s", emergency_timeout);
+            }
+            ConfigChange::SetTimelockDelay { timelock_delay } => {
+                self.timelock_delay = timelock_delay;
+                msg!("Timelock delay set to {}s", timelock_delay);
+            }
+            ConfigChange::SetMarkets { .. } | ConfigChange::RemoveMarkets { .. } => {
+                return err!(YieldVaultErrors::RegistryRequired);
+            }
+        }
+        Ok(())
+    }
+
+    /// Withdrawals can include yield, so the counter saturates at zero.
+    pub fn record_withdrawal(&mut self, amount: u64) {
+        self.total_deposits = self.total_deposits.saturating_sub(amount);
+    }
+
+    /// Management fee on `assets` for the time since `checkpoint`, plus the performance fee on `gain`.
+    pub fn fee_for(&self, assets: u64, gain: u64, checkpoint: i64, now: i64) -> Result<u64> {
+        let elapsed = if checkpoint > 0 { now.saturating_sub(checkpoint).max(0) as u64 } else { 0 };
+        let management = mul_div(
+            assets,
+            (self.management_fee_bps as u64).checked_mul(elapsed).ok_or(YieldVaultErrors::MathOverflow)?,
+            BPS_DENOMINATOR * SECONDS_PER_YEAR,
+        )?;
+        let performance = mul_div(gain, self.performance_fee_bps as u64, BPS_DENOMINATOR)?;
+        management.checked_add(performance).ok_or(YieldVaultErrors::MathOverflow.into())
+    }
+}
+
+#[account]
+pub struct ProtocolRegistry {
+    pub bump: u8,                    // Bump for the registry PDA
+    pub entries: Vec<RegistryEntry>, // Approved markets, one entry per mint
+}
+
+impl ProtocolRegistry {
+    pub const MAX_ENTRIES: usize = 8;
+    pub const LEN: usize =
+    8 + // discriminator
+    1 + // bump
+    4 + RegistryEntry::LEN * Self::MAX_ENTRIES; // entries
+
+    pub fn entry(&self, mint: &Pubkey) -> Result<&RegistryEntry> {
+        self.entries.iter().find(|e| e.mint == *mint).ok_or(YieldVaultErrors::MarketsNotRegistered.into())
+    }
+
+    pub fn require_kamino(&self, mint: &Pubkey, reserve: &Pubkey) -> Result<()> {
+        require_keys_eq!(self.entry(mint)?.kamino_reserve, *reserve, YieldVaultErrors::ProtocolNotApproved);
+        Ok(())
+    }
+
+    pub fn require_marginfi(&self, mint: &Pubkey, bank: &Pubkey) -> Result<()> {
+        require_keys_eq!(self.entry(mint)?.marginfi_bank, *bank, YieldVaultErrors::ProtocolNotApproved);
+        Ok(())
+    }
+
+    /// Applies an executed SetMarkets / RemoveMarkets change.
+    pub fn apply(&mut self, change: ConfigChange) -> Result<()> {
+        match change {
+            ConfigChange::SetMarkets { entry } => {
+                if let Some(existing) = self.entries.iter_mut().find(|e| e.mint == entry.mint) {
+                    *existing = entry;
+                } else {
+                    require!(self.entries.len() < Self::MAX_ENTRIES, YieldVaultErrors::RegistryFull);
+                    self.entries.push(entry);
+                }
+                msg!("Markets for mint {} set: Kamino reserve {}, Marginfi bank {}", entry.mint, entry.kamino_reserve, entry.marginfi_bank);
+            }
+            ConfigChange::RemoveMarkets { mint } => {
+                let index = self.entries.iter().position(|e| e.mint == mint).ok_or(YieldVaultErrors::MarketsNotRegistered)?;
+                self.entries.remove(index);
+                msg!("Markets for mint {} removed", mint);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// Transfers `fee` from a vault-owned token account to the treasury. A zero fee is a no-op,
+/// so `treasury_usdc_ta` can be left out of instructions while no fee is configured or due.
+fn pay_fee<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    authority: AccountInfo<'info>,
+    treasury: &Option<InterfaceAccount<'info, TokenAccount>>,
+    signer: &[&[&[u8]]],
+    fee: u64,
+) -> Result<()> {
+    if fee == 0 {
+        return Ok(());
+    }
+    let treasury = treasury.as_ref().ok_or(YieldVaultErrors::TreasuryRequired)?;
+    let fee_accounts = TransferChecked {
+        from: from.to_account_info(),
+        mint: mint.to_account_info(),
+        to: treasury.to_account_info(),
+        authority,
+    };
+    transfer_checked(CpiContext::new_with_signer(token_program.to_account_info(), fee_accounts, signer), fee, mint.decimals)?;
+    msg!("Paid {} USDC fee to treasury {}", fee, treasury.key());
+    Ok(())
+}
+
+/// Tops up the rent of a UserVault for the current layout, paid by the keeper, then grows it (new bytes are zeroed).
+fn grow_user_vault<'info>(
+    keeper: &Signer<'info>,
+    vault_info: &AccountInfo<'info>,
+    system_program_account: &Program<'info, System>,
+) -> Result<()> {
+    let rent_due = Rent::get()?.minimum_balance(UserVault::LEN).saturating_sub(vault_info.lamports());
+    if rent_due > 0 {
+        let top_up_accounts = system_program::Transfer {
+            from: keeper.to_account_info(),
+            to: vault_info.clone(),
+        };
+        system_program::transfer(CpiContext::new(system_program_account.to_account_info(), top_up_accounts), rent_due)?;
+    }
+    vault_info.resize(UserVault::LEN)?;
+    Ok(())
+}
+
+/// Splits a `shortfall` to redeem into its Kamino and Marginfi parts: each side gives up its
+/// weighted share and whatever one side can't cover comes from the other.
+fn split_shortfall(shortfall: u64, kamino: u64, marginfi: u64, kamino_weight_bps: u64) -> Result<(u64, u64)> {
+    let kamino_part = mul_div(shortfall, kamino_weight_bps, BPS_DENOMINATOR)?
+        .min(kamino)
+        .max(shortfall.saturating_sub(marginfi));
+    Ok((kamino_part, shortfall - kamino_part))
+}
+
+fn mul_div(a: u64, b: u64, denominator: u64) -> Result<u64> {
+    let value = (a as u128)
+        .checked_mul(b as u128)
+        .and_then(|v| v.checked_div(denominator as u128))
+        .ok_or(YieldVaultErrors::MathOverflow)?;
+    u64::try_from(value).map_err(|_| YieldVaultErrors::MathOverflow.into())
+}
+
+pub const VAULT_SEED: &[u8] = b"vault";
+pub const VAULT_STATE_SEED: &[u8] = b"vault_state";
+pub const SHARE_MINT_SEED: &[u8] = b"share_mint";
+pub const CONFIG_SEED: &[u8] = b"config";
+pub const REGISTRY_SEED: &[u8] = b"registry";
+
+// VaultConfig.paused bits
+pub const PAUSE_DEPOSITS: u8 = 1 << 0;
+pub const PAUSE_DEPLOYS: u8 = 1 << 1;
+pub const PAUSE_REBALANCES: u8 = 1 << 2;
+pub const PAUSE_ALL: u8 = PAUSE_DEPOSITS | PAUSE_DEPLOYS | PAUSE_REBALANCES;
+
+// Fee caps, in basis points
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+pub const MAX_MANAGEMENT_FEE_BPS: u16 = 200;   // 2% per year
+pub const MAX_PERFORMANCE_FEE_BPS: u16 = 2_000; // 20% of yield
+pub const MAX_TIMELOCK_DELAY: i64 = 30 * 24 * 60 * 60; // 30 days, so the admin can't lock the config forever
+// Base units a pool share withdrawal may fall short by: each of the Kamino and Marginfi redeems rounds down by at most one
+pub const REDEEM_ROUNDING_TOLERANCE: u64 = 2;
+// pub const USDC_VAULT_TOKEN_ACCOUNT_SEED: &[u8] = b"usdc_vault";
+
+/// Vault valuation returned by `get_position`, in base units of the vault mint.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct Position {
+    pub idle: u64,     // USDC in the vault ATA
+    pub kamino: u64,   // kUSDC at the reserve exchange rate
+    pub marginfi: u64, // Marginfi asset shares at the bank share value
+    pub total: u64,
+}
+
+// PendingChange copies changes around, so SetMarkets is not boxed (a Box would drop `Copy`)
+#[allow(clippy::large_enum_variant)]
+/// Admin change to the VaultConfig, applied by `execute_config_change` after the timelock.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Copy, Debug)]
+pub enum ConfigChange {
+    AddKeeper { keeper: Pubkey },
+    RemoveKeeper { keeper: Pubkey },
+    SetGuardian { guardian: Pubkey },
+    SetFees { management_fee_bps: u16, performance_fee_bps: u16 }, // capped by MAX_*_FEE_BPS
+    SetTreasury { treasury: Pubkey }, // owner of the token accounts receiving fees
+    SetDepositCaps { max_vault_deposit: u64, max_total_deposits: u64 }, // 0 disables a cap
+    SetEmergencyTimeout { emergency_timeout: i64 }, // 0 keeps the emergency redeems always open
+    SetTimelockDelay { timelock_delay: i64 }, // up to MAX_TIMELOCK_DELAY
+    SetMarkets { entry: RegistryEntry }, // adds or replaces the ProtocolRegistry entry of `entry.mint`
+    RemoveMarkets { mint: Pubkey },
+}
+
+impl ConfigChange {
+    pub const LEN: usize = 1 + RegistryEntry::LEN; // tag + largest variant (SetMarkets)
+
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            ConfigChange::SetFees { management_fee_bps, performance_fee_bps } => {
+                require!(management_fee_bps <= MAX_MANAGEMENT_FEE_BPS, YieldVaultErrors::FeeTooHigh);
+                require!(performance_fee_bps <= MAX_PERFORMANCE_FEE_BPS, YieldVaultErrors::FeeTooHigh);
+            }
+            ConfigChange::SetEmergencyTimeout { emergency_timeout } => {
+                require!(emergency_timeout >= 0, YieldVaultErrors::InvalidAmount);
+            }
+            ConfigChange::SetTimelockDelay { timelock_delay } => {
+                require!((0..=MAX_TIMELOCK_DELAY).contains(&timelock_delay), YieldVaultErrors::InvalidTimelockDelay);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct PendingChange {
+    pub id: u64,
+    pub change: ConfigChange,
+    pub eta: i64, // Unix time from which the change can be executed
+}
+
+impl PendingChange {
+    pub const LEN: usize = 8 + ConfigChange::LEN + 8;
+}
+
+/// Kamino and Marginfi addresses approved for one vault mint. Deploys only accept the listed
+/// reserve and bank; the remaining addresses are the ones the CPIs check them against, so
+/// clients can load everything from the registry.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Copy, Debug)]
+pub struct RegistryEntry {
+    pub mint: Pubkey,
+    pub kamino_lending_market: Pubkey,
+    pub kamino_reserve: Pubkey,
+    pub kamino_collateral_mint: Pubkey,
+    pub kamino_liquidity_supply: Pubkey,
+    pub marginfi_group: Pubkey,
+    pub marginfi_bank: Pubkey,
+    pub marginfi_liquidity_vault: Pubkey,
+}
+
+impl RegistryEntry {
+    pub const LEN: usize = 8 * 32;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Copy, Debug)]
+pub enum Protocol {
+    None,
+    Kamino,
+    Marginfi,
+    Split, // Both Kamino and Marginfi, per UserVault.kamino_weight_bps
+}
+
+impl Protocol {
+    /// Whether funds recorded under `self` include a position in `protocol`.
+    pub fn includes(self, protocol: Protocol) -> bool {
+        self == protocol || (self == Protocol::Split && protocol != Protocol::None)
+    }
+}
+
+#[error_code]
+pub enum YieldVaultErrors {
+    #[msg("No liquidity was redeemed")]
+    NothingRedeemed,
+    #[msg("Amount must be greater than 0")]
+    InvalidAmount,
+    #[msg("Unauthorized action")]
+    Unauthorized,
+    #[msg("Funds are already deployed to a protocol")]
+    ProtocolAlreadyActive,
+    #[msg("Funds are not in the specified protocol")]
+    IncorrectProtocol,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Not enough idle USDC in the pool")]
+    InsufficientIdleFunds,
+    #[msg("Funds are still deployed to a protocol")]
+    FundsStillDeployed,
+    #[msg("Vault already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Keeper is already authorized")]
+    KeeperAlreadyAdded,
+    #[msg("Keeper is not authorized")]
+    KeeperNotFound,
+    #[msg("Keeper set is full")]
+    TooManyKeepers,
+    #[msg("This action is paused")]
+    Paused,
+    #[msg("Unknown pause flags")]
+    InvalidPauseFlags,
+    #[msg("Fee exceeds the on-chain cap")]
+    FeeTooHigh,
+    #[msg("Treasury token account does not match the config")]
+    InvalidTreasury,
+    #[msg("A fee is due but no treasury token account was passed")]
+    TreasuryRequired,
+    #[msg("Deposit would exceed the deposit cap")]
+    DepositCapExceeded,
+    #[msg("Keeper is still active; emergency redeem is not available yet")]
+    KeeperStillActive,
+    #[msg("Vault still holds USDC or kUSDC")]
+    VaultNotEmpty,
+    #[msg("Mint does not match the vault")]
+    InvalidMint,
+    #[msg("Weight exceeds 10000 bps")]
+    InvalidWeight,
+    #[msg("Vault received less than the minimum expected amount")]
+    SlippageExceeded,
+    #[msg("Too many pending config changes")]
+    TooManyPendingChanges,
+    #[msg("No pending config change with this id")]
+    ConfigChangeNotFound,
+    #[msg("Config change is still timelocked")]
+    TimelockNotExpired,
+    #[msg("Timelock delay is out of range")]
+    InvalidTimelockDelay,
+    #[msg("Kamino account does not belong to the reserve")]
+    InvalidKaminoAccount,
+    #[msg("Marginfi account does not belong to the bank")]
+    InvalidMarginfiAccount,
+    #[msg("Mint has no markets in the protocol registry")]
+    MarketsNotRegistered,
+    #[msg("Reserve or bank is not approved in the protocol registry")]
+    ProtocolNotApproved,
+    #[msg("Protocol registry is full")]
+    RegistryFull,
+    #[msg("Registry changes need the protocol registry account")]
+    RegistryRequired,
+    #[msg("Kamino reserve must be refreshed earlier in the transaction")]
+    KaminoReserveNotRefreshed,
+    #[msg("Vault holds less than the requested amount")]
+    InsufficientFunds,
+    #[msg("Protocol paid out less than the requested amount")]
+    WithdrawalShortfall,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(management_fee_bps: u16, performance_fee_bps: u16) -> VaultConfig {
+        VaultConfig {
+            bump: 0,
+            admin: Pubkey::default(),
+            pending_admin: None,
+            keepers: vec![],
+            guardian: Pubkey::default(),
+            paused: 0,
+            treasury: Pubkey::default(),
+            management_fee_bps,
+            performance_fee_bps,
+            max_vault_deposit: 0,
+            max_total_deposits: 0,
+            total_deposits: 0,
+            emergency_timeout: 0,
+            last_keeper_heartbeat: 0,
+            timelock_delay: 0,
+            next_change_id: 0,
+            pending_changes: vec![],
+        }
+    }
+
+    fn user_vault(deposited_amount: u64, realized_yield: u64, fee_checkpoint: i64) -> UserVault {
+        UserVault {
+            bump: 0,
+            owner: Pubkey::default(),
+            marginfi_account: Pubkey::default(),
+            deposited_amount,
+            active_protocol: Protocol::None,
+            fee_checkpoint,
+            realized_yield,
+            withdrawn_amount: 0,
+            mint: Pubkey::default(),
+            legacy_seeds: false,
+            kamino_weight_bps: 0,
+            version: UserVault::VERSION,
+            reserved: [0; 64],
+        }
+    }
+
+    fn pool(total_assets: u64, fee_checkpoint: i64) -> VaultState {
+        VaultState {
+            bump: 0,
+            usdc_mint: Pubkey::default(),
+            share_mint: Pubkey::default(),
+            marginfi_account: Pubkey::default(),
+            total_assets,
+            kamino_deployed: 0,
+            marginfi_deployed: 0,
+            fee_checkpoint,
+        }
+    }
+
+    fn overflow() -> Error {
+        YieldVaultErrors::MathOverflow.into()
+    }
+
+    #[test]
+    fn mul_div_rounds_down() {
+        assert_eq!(mul_div(10, 1, 3).unwrap(), 3);
+        assert_eq!(mul_div(2, 1, 3).unwrap(), 0);
+        assert_eq!(mul_div(0, u64::MAX, 1).unwrap(), 0);
+    }
+
+    #[test]
+    fn mul_div_keeps_wide_intermediates() {
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn mul_div_rejects_overflow_and_zero_denominator() {
+        assert_eq!(mul_div(u64::MAX, 2, 1).unwrap_err(), overflow());
+        assert_eq!(mul_div(1, 1, 0).unwrap_err(), overflow());
+    }
+
+    #[test]
+    fn split_shortfall_follows_the_weight() {
+        assert_eq!(split_shortfall(100, 1_000, 1_000, 5_000).unwrap(), (50, 50));
+        assert_eq!(split_shortfall(100, 1_000, 1_000, 0).unwrap(), (0, 100));
+        assert_eq!(split_shortfall(100, 1_000, 1_000, 10_000).unwrap(), (100, 0));
+        // The Kamino part rounds down, so the remainder lands on Marginfi
+        assert_eq!(split_shortfall(3, 1_000, 1_000, 5_000).unwrap(), (1, 2));
+    }
+
+    #[test]
+    fn split_shortfall_moves_what_one_side_cannot_cover() {
+        assert_eq!(split_shortfall(100, 20, 1_000, 5_000).unwrap(), (20, 80));
+        assert_eq!(split_shortfall(100, 1_000, 20, 5_000).unwrap(), (80, 20));
+        assert_eq!(split_shortfall(100, 60, 40, 9_000).unwrap(), (60, 40));
+    }
+
+    #[test]
+    fn fee_for_charges_management_pro_rata() {
+        let config = config(100, 0);
+        assert_eq!(config.fee_for(1_000_000, 0, 1, 1 + SECONDS_PER_YEAR as i64).unwrap(), 10_000);
+        assert_eq!(config.fee_for(1_000_000, 0, 1, 1 + SECONDS_PER_YEAR as i64 / 2).unwrap(), 5_000);
+    }
+
+    #[test]
+    fn fee_for_skips_management_without_elapsed_time() {
+        let config = config(100, 0);
+        // No checkpoint yet, or a clock behind the checkpoint
+        assert_eq!(config.fee_for(1_000_000, 0, 0, SECONDS_PER_YEAR as i64).unwrap(), 0);
+        assert_eq!(config.fee_for(1_000_000, 0, 100, 50).unwrap(), 0);
+    }
+
+    #[test]
+    fn fee_for_rounds_performance_down() {
+        let config = config(0, 2_000);
+        assert_eq!(config.fee_for(0, 1_000, 0, 0).unwrap(), 200);
+        assert_eq!(config.fee_for(0, 4, 0, 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn fee_for_rejects_overflow() {
+        let config = config(u16::MAX, 0);
+        assert_eq!(config.fee_for(1, 0, 1, i64::MAX).unwrap_err(), overflow());
+    }
+
+    #[test]
+    fn user_vault_fees_only_on_gain_over_high_water() {
+        let config = config(0, 1_000);
+        let mut vault = user_vault(1_000, 100, 1);
+        assert_eq!(vault.settle_fees(&config, 1_600, 10).unwrap(), 50);
+        assert_eq!(vault.realized_yield, 550);
+        assert_eq!(vault.fee_checkpoint, 10);
+        // Settling again at the same value charges nothing more
+        assert_eq!(vault.settle_fees(&config, 1_550, 20).unwrap(), 0);
+        assert_eq!(vault.realized_yield, 550);
+    }
+
+    #[test]
+    fn user_vault_loss_clears_realized_yield() {
+        let config = config(0, 1_000);
+        let mut vault = user_vault(1_000, 100, 1);
+        assert_eq!(vault.settle_fees(&config, 900, 10).unwrap(), 0);
+        assert_eq!(vault.realized_yield, 0);
+        assert_eq!(vault.deposited_amount, 1_000);
+    }
+
+    #[test]
+    fn user_vault_fee_is_capped_at_value() {
+        let config = config(10_000, 0);
+        let mut vault = user_vault(1_000, 0, 1);
+        assert_eq!(vault.settle_fees(&config, 100, 1 + 2 * SECONDS_PER_YEAR as i64).unwrap(), 100);
+        assert_eq!(vault.realized_yield, 0);
+    }
+
+    #[test]
+    fn user_vault_first_settlement_starts_the_management_clock() {
+        let config = config(100, 0);
+        let mut vault = user_vault(1_000_000, 0, 0);
+        assert_eq!(vault.settle_fees(&config, 1_000_000, 1).unwrap(), 0);
+        assert_eq!(vault.fee_checkpoint, 1);
+        assert_eq!(vault.settle_fees(&config, 1_000_000, 1 + SECONDS_PER_YEAR as i64).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn pool_fees_move_total_assets_to_the_post_fee_value() {
+        let config = config(0, 1_000);
+        let mut pool = pool(1_000, 1);
+        assert_eq!(pool.settle_fees(&config, 1_500, 10).unwrap(), 50);
+        assert_eq!(pool.total_assets, 1_450);
+        // A loss is charged nothing and lowers the share price
+        assert_eq!(pool.settle_fees(&config, 1_200, 20).unwrap(), 0);
+        assert_eq!(pool.total_assets, 1_200);
+    }
+
+    #[test]
+    fn pool_prices_shares_against_total_assets() {
+        let empty = pool(0, 0);
+        assert_eq!(empty.shares_for_assets(500, 0).unwrap(), 500);
+        assert_eq!(empty.assets_for_shares(1, 0).unwrap_err(), YieldVaultErrors::InvalidAmount.into());
+
+        let pool = pool(3_000, 0);
+        // Both directions round down, in the pool's favor
+        assert_eq!(pool.shares_for_assets(1_000, 2_000).unwrap(), 666);
+        assert_eq!(pool.assets_for_shares(666, 2_000).unwrap(), 999);
+    }
 }
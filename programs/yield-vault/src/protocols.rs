@@ -0,0 +1,418 @@
+// Thin wrappers around the Kamino and Marginfi CPIs.
+// The same CPI calls are issued from the per-user vault and from the pooled vault,
+// so the account plumbing lives here and every instruction only picks its accounts.
+// Each CPI first checks the protocol accounts against the reserve/bank, so a keeper cannot
+// point a deposit or redeem at an arbitrary market, authority or liquidity vault.
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::Discriminator;
+use std::cell::Ref;
+use kamino_lend::cpi as kamino_cpi;
+use kamino_lend::program::KaminoLending;
+use kamino_lend::state::Reserve;
+
+use marginfi_cpi_local::cpi::accounts as mfi_accounts;
+use marginfi_cpi_local::cpi as mfi_cpi;
+use marginfi_cpi_local::program::Marginfi;
+
+use crate::YieldVaultErrors;
+
+// PDA seeds of the protocol-owned authorities
+const KAMINO_LENDING_MARKET_AUTH_SEED: &[u8] = b"lma";
+const MFI_LIQUIDITY_VAULT_AUTH_SEED: &[u8] = b"liquidity_vault_auth";
+
+// Anchor discriminator of klend's `refresh_reserve` (sha256("global:refresh_reserve")[..8])
+const KAMINO_REFRESH_RESERVE_DISCRIMINATOR: [u8; 8] = [2, 218, 138, 235, 79, 201, 25, 102];
+
+/// Accounts needed to deposit to / redeem from a Kamino reserve on behalf of a vault PDA.
+pub struct KaminoCpi<'info> {
+    pub program: AccountInfo<'info>,
+    pub owner: AccountInfo<'info>, // vault PDA (signs via seeds)
+    pub lending_market: AccountInfo<'info>,
+    pub lending_market_authority: AccountInfo<'info>,
+    pub reserve: AccountInfo<'info>,
+    pub reserve_liquidity_mint: AccountInfo<'info>,
+    pub reserve_liquidity_supply: AccountInfo<'info>,
+    pub reserve_collateral_mint: AccountInfo<'info>,
+    pub liquidity_account: AccountInfo<'info>,  // vault USDC ATA
+    pub collateral_account: AccountInfo<'info>, // vault kUSDC ATA
+    pub liquidity_token_program: AccountInfo<'info>,  // program of the liquidity mint (Token or Token-2022)
+    pub collateral_token_program: AccountInfo<'info>, // kUSDC is always an SPL Token mint
+    pub instruction_sysvar_account: AccountInfo<'info>,
+}
+
+impl<'info> KaminoCpi<'info> {
+    /// Checks the market, its authority PDA, the reserve's supply vault and mints, and that the reserve was refreshed.
+    fn validate(&self) -> Result<()> {
+        require_keys_eq!(*self.lending_market.owner, KaminoLending::id(), YieldVaultErrors::InvalidKaminoAccount);
+        let reserve = check_kamino_reserve(&self.reserve, &self.reserve_liquidity_mint.key(), &self.reserve_collateral_mint.key())?;
+        require_keys_eq!(reserve.lending_market, self.lending_market.key(), YieldVaultErrors::InvalidKaminoAccount);
+        require_keys_eq!(reserve.liquidity_supply, self.reserve_liquidity_supply.key(), YieldVaultErrors::InvalidKaminoAccount);
+        let (authority, _) = Pubkey::find_program_address(
+            &[KAMINO_LENDING_MARKET_AUTH_SEED, self.lending_market.key.as_ref()],
+            &KaminoLending::id(),
+        );
+        require_keys_eq!(self.lending_market_authority.key(), authority, YieldVaultErrors::InvalidKaminoAccount);
+        self.require_refreshed()
+    }
+
+    /// Kamino rejects deposits and redeems on a reserve that was not refreshed in the current slot,
+    /// so a klend `refresh_reserve` of this reserve must precede the instruction in the transaction.
+    /// Checked through the instructions sysvar: a refresh in the same transaction is never stale.
+    pub fn require_refreshed(&self) -> Result<()> {
+        let ixs = &self.instruction_sysvar_account;
+        let current = sysvar_instructions::load_current_index_checked(ixs)?;
+        for index in (0..current).rev() {
+            let ix = sysvar_instructions::load_instruction_at_checked(index as usize, ixs)?;
+            if ix.program_id == KaminoLending::id()
+                && ix.data.starts_with(&KAMINO_REFRESH_RESERVE_DISCRIMINATOR)
+                && ix.accounts.first().map(|a| a.pubkey) == Some(self.reserve.key())
+            {
+                return Ok(());
+            }
+        }
+        err!(YieldVaultErrors::KaminoReserveNotRefreshed)
+    }
+
+    /// Deposits `amount` USDC from the vault ATA, minting kUSDC into the vault collateral ATA.
+    pub fn deposit(&self, signer: &[&[&[u8]]], amount: u64) -> Result<()> {
+        self.validate()?;
+        let cpi_accounts = kamino_cpi::accounts::DepositReserveLiquidity {
+            owner:                          self.owner.clone(),
+            reserve:                        self.reserve.clone(),
+            // Lending Market accounts
+            lending_market:                 self.lending_market.clone(),
+            lending_market_authority:       self.lending_market_authority.clone(),
+            // Reserve accounts
+            reserve_liquidity_mint:         self.reserve_liquidity_mint.clone(),
+            reserve_liquidity_supply:       self.reserve_liquidity_supply.clone(),
+            reserve_collateral_mint:        self.reserve_collateral_mint.clone(),
+            // User accounts
+            user_source_liquidity:          self.liquidity_account.clone(),
+            user_destination_collateral:    self.collateral_account.clone(),
+            // Token programs
+            collateral_token_program:       self.collateral_token_program.clone(),
+            liquidity_token_program:        self.liquidity_token_program.clone(),
+            instruction_sysvar_account:     self.instruction_sysvar_account.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(self.program.clone(), cpi_accounts, signer);
+        kamino_cpi::deposit_reserve_liquidity(cpi_ctx, amount)
+    }
+
+    /// Burns `collateral_amount` kUSDC from the vault collateral ATA, returning USDC to the vault ATA.
+    pub fn redeem(&self, signer: &[&[&[u8]]], collateral_amount: u64) -> Result<()> {
+        self.validate()?;
+        let cpi_accounts = kamino_cpi::accounts::RedeemReserveCollateral {
+            owner:                         self.owner.clone(), // PDA
+            reserve:                       self.reserve.clone(),
+            lending_market:                self.lending_market.clone(),
+            lending_market_authority:      self.lending_market_authority.clone(),
+            // Reserve accounts
+            reserve_liquidity_mint:        self.reserve_liquidity_mint.clone(),
+            reserve_liquidity_supply:      self.reserve_liquidity_supply.clone(),
+            reserve_collateral_mint:       self.reserve_collateral_mint.clone(),
+            // User accounts
+            user_source_collateral:        self.collateral_account.clone(),
+            user_destination_liquidity:    self.liquidity_account.clone(),
+
+            collateral_token_program:      self.collateral_token_program.clone(),
+            liquidity_token_program:       self.liquidity_token_program.clone(),
+            instruction_sysvar_account:    self.instruction_sysvar_account.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(self.program.clone(), cpi_accounts, signer);
+        kamino_cpi::redeem_reserve_collateral(cpi_ctx, collateral_amount)
+    }
+}
+
+/// Accounts needed to deposit to / withdraw from a Marginfi bank on behalf of a vault PDA.
+pub struct MarginfiCpi<'info> {
+    pub program: AccountInfo<'info>,
+    pub group: AccountInfo<'info>,
+    pub marginfi_account: AccountInfo<'info>,
+    pub authority: AccountInfo<'info>, // vault PDA (signs via seeds)
+    pub bank: AccountInfo<'info>,
+    pub liquidity_vault: AccountInfo<'info>,
+    pub token_account: AccountInfo<'info>, // vault USDC ATA
+    pub mint: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+impl<'info> MarginfiCpi<'info> {
+    /// Checks the bank's group, mint, liquidity vault and (for withdrawals) the vault authority PDA.
+    fn validate(&self, bank_liquidity_vault_authority: Option<&AccountInfo>) -> Result<()> {
+        require_keys_eq!(*self.group.owner, Marginfi::id(), YieldVaultErrors::InvalidMarginfiAccount);
+        let bank = check_marginfi_bank(&self.bank, &self.mint.key())?;
+        require_keys_eq!(bank.group, self.group.key(), YieldVaultErrors::InvalidMarginfiAccount);
+        require_keys_eq!(bank.liquidity_vault, self.liquidity_vault.key(), YieldVaultErrors::InvalidMarginfiAccount);
+        if let Some(authority) = bank_liquidity_vault_authority {
+            let expected = Pubkey::create_program_address(
+                &[MFI_LIQUIDITY_VAULT_AUTH_SEED, self.bank.key.as_ref(), &[bank.liquidity_vault_authority_bump]],
+                &Marginfi::id(),
+            )
+            .map_err(|_| YieldVaultErrors::InvalidMarginfiAccount)?;
+            require_keys_eq!(authority.key(), expected, YieldVaultErrors::InvalidMarginfiAccount);
+        }
+        Ok(())
+    }
+
+    /// Deposits `amount` USDC from the vault ATA into the bank.
+    pub fn deposit(&self, signer: &[&[&[u8]]], amount: u64) -> Result<()> {
+        self.validate(None)?;
+        let cpi_accounts = mfi_accounts::LendingAccountDeposit {
+            group:                  self.group.clone(),
+            marginfi_account:       self.marginfi_account.clone(),
+            authority:              self.authority.clone(),
+            bank:                   self.bank.clone(),
+            signer_token_account:   self.token_account.clone(),
+            liquidity_vault:        self.liquidity_vault.clone(),
+            token_program:          self.token_program.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(self.program.clone(), cpi_accounts, signer)
+            .with_remaining_accounts(self.mint_remaining_accounts());
+        mfi_cpi::lending_account_deposit(cpi_ctx, amount, Some(true))
+    }
+
+    /// Withdraws USDC from the bank back into the vault ATA.
+    /// With `withdraw_all` set, `amount` is ignored by Marginfi and the whole balance is closed.
+    pub fn withdraw(
+        &self,
+        bank_liquidity_vault_authority: AccountInfo<'info>,
+        signer: &[&[&[u8]]],
+        amount: u64,
+        withdraw_all: bool,
+    ) -> Result<()> {
+        self.validate(Some(&bank_liquidity_vault_authority))?;
+        let cpi_accounts = mfi_accounts::LendingAccountWithdraw {
+            group:                          self.group.clone(),
+            marginfi_account:               self.marginfi_account.clone(),
+            authority:                      self.authority.clone(),
+            bank:                           self.bank.clone(),
+            destination_token_account:      self.token_account.clone(),
+            bank_liquidity_vault_authority,
+            liquidity_vault:                self.liquidity_vault.clone(),
+            token_program:                  self.token_program.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(self.program.clone(), cpi_accounts, signer)
+            .with_remaining_accounts(self.mint_remaining_accounts());
+        mfi_cpi::lending_account_withdraw(cpi_ctx, amount, Some(withdraw_all))
+    }
+
+    /// Marginfi needs the mint as a remaining account to `transfer_checked` Token-2022 balances.
+    fn mint_remaining_accounts(&self) -> Vec<AccountInfo<'info>> {
+        if self.token_program.key() == anchor_spl::token_2022::ID {
+            vec![self.mint.clone()]
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Reserve fields the vault relies on, after `check_kamino_reserve`.
+pub struct KaminoReserveKeys {
+    pub lending_market: Pubkey,
+    pub liquidity_supply: Pubkey,
+}
+
+/// Checks that `reserve` is a Kamino reserve (owner and discriminator checked by `load_kamino_reserve`)
+/// lending `liquidity_mint` against the `collateral_mint` kUSDC.
+pub fn check_kamino_reserve<'info>(reserve: &AccountInfo<'info>, liquidity_mint: &Pubkey, collateral_mint: &Pubkey) -> Result<KaminoReserveKeys> {
+    let reserve = load_kamino_reserve(reserve)?;
+    require_keys_eq!(reserve.liquidity.mint_pubkey, *liquidity_mint, YieldVaultErrors::InvalidKaminoAccount);
+    require_keys_eq!(reserve.collateral.mint_pubkey, *collateral_mint, YieldVaultErrors::InvalidKaminoAccount);
+    Ok(KaminoReserveKeys {
+        lending_market: reserve.lending_market,
+        liquidity_supply: reserve.liquidity.supply_vault,
+    })
+}
+
+/// Borrows `reserve` as a Kamino Reserve, checking its owner and discriminator as AccountLoader does.
+/// Read in place from the account data, so callers can pass any `AccountInfo` without an `'info` borrow.
+pub fn load_kamino_reserve<'a>(reserve: &'a AccountInfo) -> Result<Ref<'a, Reserve>> {
+    require_keys_eq!(*reserve.owner, KaminoLending::id(), ErrorCode::AccountOwnedByWrongProgram);
+    let data = reserve.try_borrow_data()?;
+    let disc = Reserve::DISCRIMINATOR;
+    require!(data.len() >= disc.len() + std::mem::size_of::<Reserve>(), ErrorCode::AccountDidNotDeserialize);
+    require!(&data[..disc.len()] == disc, ErrorCode::AccountDiscriminatorMismatch);
+    Ok(Ref::map(data, |data| {
+        bytemuck::from_bytes(&data[disc.len()..disc.len() + std::mem::size_of::<Reserve>()])
+    }))
+}
+
+/// Kamino scaled fractions (`*_sf` fields) carry 68 fractional bits.
+const KAMINO_FRACTION_BITS: u32 = 68;
+
+/// Snapshot of a Kamino reserve's kUSDC <-> USDC exchange rate.
+/// Only as fresh as the reserve's last `refresh_reserve`.
+pub struct KaminoExchangeRate {
+    pub total_liquidity: u128,   // available + borrowed - accumulated fees, in liquidity units
+    pub collateral_supply: u128, // kUSDC mint supply
+}
+
+impl KaminoExchangeRate {
+    /// Reads the rate from the reserve account (owner and discriminator are checked by `load_kamino_reserve`).
+    pub fn load(reserve: &AccountInfo) -> Result<Self> {
+        let reserve = load_kamino_reserve(reserve)?;
+        let total_liquidity_sf = ((reserve.liquidity.available_amount as u128) << KAMINO_FRACTION_BITS)
+            .saturating_add(reserve.liquidity.borrowed_amount_sf)
+            .saturating_sub(reserve.liquidity.accumulated_protocol_fees_sf)
+            .saturating_sub(reserve.liquidity.accumulated_referrer_fees_sf)
+            .saturating_sub(reserve.liquidity.pending_referrer_fees_sf);
+        Ok(Self {
+            total_liquidity: total_liquidity_sf >> KAMINO_FRACTION_BITS,
+            collateral_supply: reserve.collateral.mint_total_supply as u128,
+        })
+    }
+
+    /// kUSDC to burn to receive at least `liquidity` USDC (rounded up).
+    pub fn collateral_for_liquidity(&self, liquidity: u64) -> Result<u64> {
+        if self.total_liquidity == 0 || self.collateral_supply == 0 {
+            return Ok(liquidity);
+        }
+        let collateral = (liquidity as u128)
+            .checked_mul(self.collateral_supply)
+            .and_then(|v| v.checked_add(self.total_liquidity - 1))
+            .map(|v| v / self.total_liquidity)
+            .ok_or(crate::YieldVaultErrors::MathOverflow)?;
+        u64::try_from(collateral).map_err(|_| crate::YieldVaultErrors::MathOverflow.into())
+    }
+
+    /// USDC received for burning `collateral` kUSDC (rounded down).
+    pub fn liquidity_for_collateral(&self, collateral: u64) -> Result<u64> {
+        if self.total_liquidity == 0 || self.collateral_supply == 0 {
+            return Ok(collateral);
+        }
+        let liquidity = (collateral as u128)
+            .checked_mul(self.total_liquidity)
+            .map(|v| v / self.collateral_supply)
+            .ok_or(crate::YieldVaultErrors::MathOverflow)?;
+        u64::try_from(liquidity).map_err(|_| crate::YieldVaultErrors::MathOverflow.into())
+    }
+}
+
+/// I80F48 fixed point (Marginfi share values and balances) carries 48 fractional bits.
+const MARGINFI_FRACTION_BITS: u32 = 48;
+// Byte offsets into the Marginfi accounts (discriminator included), per the bundled IDL.
+// The generated types are not zero-copy, so the few fields needed are read in place.
+const MFI_BANK_DISCRIMINATOR: [u8; 8] = [142, 49, 166, 242, 50, 66, 97, 188];
+const MFI_BANK_MINT: usize = 8;
+const MFI_BANK_GROUP: usize = 41;
+const MFI_BANK_ASSET_SHARE_VALUE: usize = 80;
+const MFI_BANK_LIQUIDITY_VAULT: usize = 112;
+const MFI_BANK_LIQUIDITY_VAULT_AUTHORITY_BUMP: usize = 145;
+const MFI_ACCOUNT_DISCRIMINATOR: [u8; 8] = [67, 178, 130, 109, 126, 114, 28, 42];
+const MFI_ACCOUNT_BALANCES: usize = 72;
+const MFI_BALANCE_LEN: usize = 104;
+const MFI_BALANCE_BANK: usize = 1;
+const MFI_BALANCE_ASSET_SHARES: usize = 40;
+const MFI_MAX_BALANCES: usize = 16;
+
+/// Bank fields the vault relies on, after `check_marginfi_bank`.
+pub struct MarginfiBankKeys {
+    pub group: Pubkey,
+    pub liquidity_vault: Pubkey,
+    pub liquidity_vault_authority_bump: u8,
+}
+
+/// Checks that `bank` is a Marginfi bank (owner and discriminator) for `mint`.
+pub fn check_marginfi_bank(bank: &AccountInfo, mint: &Pubkey) -> Result<MarginfiBankKeys> {
+    require_keys_eq!(*bank.owner, Marginfi::id(), YieldVaultErrors::InvalidMarginfiAccount);
+    let data = bank.try_borrow_data()?;
+    let read_pubkey = |offset: usize| -> Result<Pubkey> {
+        let bytes = data.get(offset..offset + 32).ok_or(ErrorCode::AccountDidNotDeserialize)?;
+        Ok(Pubkey::new_from_array(bytes.try_into().unwrap()))
+    };
+    require!(data.get(..8) == Some(&MFI_BANK_DISCRIMINATOR[..]), ErrorCode::AccountDiscriminatorMismatch);
+    require_keys_eq!(read_pubkey(MFI_BANK_MINT)?, *mint, YieldVaultErrors::InvalidMarginfiAccount);
+    Ok(MarginfiBankKeys {
+        group: read_pubkey(MFI_BANK_GROUP)?,
+        liquidity_vault: read_pubkey(MFI_BANK_LIQUIDITY_VAULT)?,
+        liquidity_vault_authority_bump: *data
+            .get(MFI_BANK_LIQUIDITY_VAULT_AUTHORITY_BUMP)
+            .ok_or(ErrorCode::AccountDidNotDeserialize)?,
+    })
+}
+
+/// USDC value of a Marginfi account's deposit in `bank`: asset shares times the bank's
+/// `asset_share_value` (rounded down). Only as fresh as the bank's last interest accrual.
+/// Both accounts must be owned by Marginfi and carry their account discriminators.
+pub fn marginfi_asset_value(marginfi_account: &AccountInfo, bank: &AccountInfo) -> Result<u64> {
+    let read_i80f48 = |data: &[u8], offset: usize| -> Result<i128> {
+        let bytes = data
+            .get(offset..offset + 16)
+            .ok_or(ErrorCode::AccountDidNotDeserialize)?;
+        Ok(i128::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    require_keys_eq!(*marginfi_account.owner, Marginfi::id(), YieldVaultErrors::InvalidMarginfiAccount);
+    require_keys_eq!(*bank.owner, Marginfi::id(), YieldVaultErrors::InvalidMarginfiAccount);
+    let account_data = marginfi_account.try_borrow_data()?;
+    require!(account_data.get(..8) == Some(&MFI_ACCOUNT_DISCRIMINATOR[..]), ErrorCode::AccountDiscriminatorMismatch);
+    let mut asset_shares = 0i128;
+    for i in 0..MFI_MAX_BALANCES {
+        let balance = MFI_ACCOUNT_BALANCES + i * MFI_BALANCE_LEN;
+        let active = *account_data.get(balance).ok_or(ErrorCode::AccountDidNotDeserialize)?;
+        let bank_pk = account_data
+            .get(balance + MFI_BALANCE_BANK..balance + MFI_BALANCE_BANK + 32)
+            .ok_or(ErrorCode::AccountDidNotDeserialize)?;
+        if active != 0 && bank_pk == bank.key().as_ref() {
+            asset_shares = read_i80f48(&account_data, balance + MFI_BALANCE_ASSET_SHARES)?;
+            break;
+        }
+    }
+    if asset_shares <= 0 {
+        return Ok(0);
+    }
+
+    let bank_data = bank.try_borrow_data()?;
+    require!(bank_data.get(..8) == Some(&MFI_BANK_DISCRIMINATOR[..]), ErrorCode::AccountDiscriminatorMismatch);
+    let share_value = read_i80f48(&bank_data, MFI_BANK_ASSET_SHARE_VALUE)?;
+    // Drop half of the share value's fraction up front so the product stays within i128
+    let value = asset_shares
+        .checked_mul(share_value.max(0) >> (MARGINFI_FRACTION_BITS / 2))
+        .map(|v| v >> (MARGINFI_FRACTION_BITS + MARGINFI_FRACTION_BITS / 2))
+        .ok_or(crate::YieldVaultErrors::MathOverflow)?;
+    u64::try_from(value).map_err(|_| crate::YieldVaultErrors::MathOverflow.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(total_liquidity: u128, collateral_supply: u128) -> KaminoExchangeRate {
+        KaminoExchangeRate { total_liquidity, collateral_supply }
+    }
+
+    #[test]
+    fn empty_reserve_converts_one_to_one() {
+        for rate in [rate(0, 0), rate(0, 1_000), rate(1_000, 0)] {
+            assert_eq!(rate.collateral_for_liquidity(123).unwrap(), 123);
+            assert_eq!(rate.liquidity_for_collateral(123).unwrap(), 123);
+        }
+    }
+
+    #[test]
+    fn conversions_round_in_the_reserves_favor() {
+        // 3 USDC for every 2 kUSDC
+        let rate = rate(3_000, 2_000);
+        assert_eq!(rate.collateral_for_liquidity(100).unwrap(), 67);
+        assert_eq!(rate.liquidity_for_collateral(67).unwrap(), 100);
+        assert_eq!(rate.liquidity_for_collateral(1).unwrap(), 1);
+        assert_eq!(rate.collateral_for_liquidity(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn redeeming_collateral_for_liquidity_returns_at_least_the_request() {
+        let rate = rate(1_234_567, 1_000_003);
+        for liquidity in [1, 7, 999, 1_000_000] {
+            let collateral = rate.collateral_for_liquidity(liquidity).unwrap();
+            assert!(rate.liquidity_for_collateral(collateral).unwrap() >= liquidity);
+        }
+    }
+
+    #[test]
+    fn conversions_reject_overflow() {
+        let overflow: Error = YieldVaultErrors::MathOverflow.into();
+        assert_eq!(rate(1, u64::MAX as u128 * 2).collateral_for_liquidity(u64::MAX).unwrap_err(), overflow);
+        assert_eq!(rate(u64::MAX as u128 * 2, 1).liquidity_for_collateral(u64::MAX).unwrap_err(), overflow);
+    }
+}